@@ -0,0 +1,1043 @@
+//! A searchable archive of everything relayed from the monitor. Stored as a
+//! JSON Lines file and indexed in memory when the bot starts. Records older
+//! than the retention period are dropped, and the file is written by a
+//! thread of its own so the bot never waits on the disk.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex, RwLock},
+    thread::JoinHandle,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use poise::serenity_prelude::GuildId;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    events::Event,
+    impersonation,
+    namereq::Decision,
+    sink::MessageRef,
+    util::{self, TimestampStyle},
+    Globals, NameRequest, Result,
+};
+
+/// Longest a single result line gets before it's cut off
+const MAX_LINE_LEN: usize = 200;
+/// Most names listed on one line of name request context
+const MAX_CONTEXT_NAMES: usize = 5;
+/// How often records past the retention period are dropped
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Record {
+    /// When the bot received the event, in milliseconds since the Unix epoch
+    pub(crate) at_ms: u64,
+    pub(crate) event: Event,
+    /// The message the event was relayed as, if any
+    #[serde(default)]
+    pub(crate) message: Option<MessageRef>,
+    /// Set on the extra record added when a moderator decides a name request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) decision: Option<Decision>,
+}
+impl Record {
+    pub(crate) fn time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.at_ms)
+    }
+
+    pub(crate) fn sender(&self) -> Option<&str> {
+        match &self.event {
+            Event::Chat(chat) => Some(&chat.from),
+            Event::Email(email) => Some(&email.from),
+            Event::Broadcast(bcast) => Some(&bcast.from),
+            Event::NameRequest(_) => None,
+        }
+    }
+
+    pub(crate) fn recipient(&self) -> Option<&str> {
+        match &self.event {
+            Event::Chat(chat) => chat.to.as_deref(),
+            Event::Email(email) => Some(&email.to),
+            _ => None,
+        }
+    }
+
+    fn text_contains(&self, needle: &str) -> bool {
+        let has = |haystack: &str| haystack.to_lowercase().contains(needle);
+        match &self.event {
+            Event::Chat(chat) => has(&chat.message),
+            Event::Email(email) => {
+                email.subject.as_deref().is_some_and(has) || email.body.iter().any(|l| has(l))
+            }
+            Event::Broadcast(bcast) => has(&bcast.message),
+            Event::NameRequest(namereq) => has(&namereq.requested_name),
+        }
+    }
+
+    /// One line describing the record, with a jump link to where it was relayed.
+    pub(crate) fn format(&self, guild_id: GuildId) -> String {
+        let to = |to: Option<&str>| match to {
+            Some(to) => format!(" (to {})", to),
+            None => String::new(),
+        };
+        let description = match &self.event {
+            Event::Chat(chat) => format!(
+                "[{}] {}{}: {}",
+                chat.kind,
+                chat.from,
+                to(chat.to.as_deref()),
+                chat.message
+            ),
+            Event::Email(email) => format!(
+                "[Email] {}{}: <{}> {}",
+                email.from,
+                to(Some(&email.to)),
+                email.subject.as_deref().unwrap_or("no subject"),
+                email.body.join(" ")
+            ),
+            Event::Broadcast(bcast) => {
+                format!(
+                    "[Broadcast] ({}) {}: {}",
+                    bcast.scope, bcast.from, bcast.message
+                )
+            }
+            Event::NameRequest(namereq) => format!(
+                "[Name request] Player {}: {}",
+                namereq.player_uid, namereq.requested_name
+            ),
+        };
+        let mut line = format!(
+            "{} {}",
+            util::discord_timestamp(self.time(), TimestampStyle::ShortDateTime),
+            util::truncate(&description, MAX_LINE_LEN)
+        );
+        if let Some(message) = self.message {
+            line.push_str(&format!(
+                " [jump]({})",
+                message.id.link(message.channel, Some(guild_id))
+            ));
+        }
+        line
+    }
+}
+
+/// What kind of event to search for.
+#[derive(Debug, Clone, Copy, PartialEq, poise::ChoiceParameter)]
+pub(crate) enum Kind {
+    #[name = "Any chat"]
+    AnyChat,
+    FreeChat,
+    MenuChat,
+    BuddyChat,
+    GroupChat,
+    Email,
+    Broadcast,
+}
+impl Kind {
+    fn matches(&self, event: &Event) -> bool {
+        match (self, event) {
+            (Kind::AnyChat, Event::Chat(_)) => true,
+            (Kind::Email, Event::Email(_)) => true,
+            (Kind::Broadcast, Event::Broadcast(_)) => true,
+            (kind, Event::Chat(chat)) => format!("{:?}", kind) == chat.kind,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Query {
+    /// Sender, ignoring case
+    pub(crate) player: Option<String>,
+    /// Recipient, ignoring case
+    pub(crate) recipient: Option<String>,
+    /// Sender or recipient, ignoring case
+    pub(crate) involving: Option<String>,
+    pub(crate) kind: Option<Kind>,
+    /// Text in the message, subject or body, ignoring case
+    pub(crate) text: Option<String>,
+    pub(crate) since: Option<SystemTime>,
+    pub(crate) until: Option<SystemTime>,
+}
+impl Query {
+    fn matches(&self, record: &Record) -> bool {
+        let same = |a: Option<&str>, b: &Option<String>| match b {
+            Some(b) => a.is_some_and(|a| a.eq_ignore_ascii_case(b)),
+            None => true,
+        };
+        let kind_matches = match self.kind {
+            Some(kind) => kind.matches(&record.event),
+            // name requests are only kept for /history
+            None => !matches!(record.event, Event::NameRequest(_)),
+        };
+        kind_matches
+            && same(record.sender(), &self.player)
+            && same(record.recipient(), &self.recipient)
+            && (self.involving.is_none()
+                || same(record.sender(), &self.involving)
+                || same(record.recipient(), &self.involving))
+            && self
+                .text
+                .as_ref()
+                .is_none_or(|text| record.text_contains(&text.to_lowercase()))
+            && self.since.is_none_or(|since| record.time() >= since)
+            && self.until.is_none_or(|until| record.time() <= until)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Index {
+    /// Oldest first. Shared with the writer thread and with prunes being built.
+    records: Vec<Arc<Record>>,
+    /// Lowercased sender name to positions in `records`
+    by_sender: HashMap<String, Vec<usize>>,
+    /// Positions of name requests in `records`, which have no sender name
+    name_requests: Vec<usize>,
+    /// Positions of name request decisions in `records`
+    decisions: Vec<usize>,
+    /// Every player name seen, by [`impersonation::skeleton`]
+    names: HashMap<String, BTreeSet<String>>,
+}
+impl Index {
+    fn insert(&mut self, record: Arc<Record>) {
+        let position = self.records.len();
+        match (&record.event, record.decision) {
+            (Event::NameRequest(_), None) => self.name_requests.push(position),
+            (Event::NameRequest(_), Some(_)) => self.decisions.push(position),
+            _ => {}
+        }
+        if let Some(sender) = record.sender() {
+            self.by_sender
+                .entry(sender.to_lowercase())
+                .or_default()
+                .push(position);
+        }
+        for name in [record.sender(), record.recipient()].into_iter().flatten() {
+            self.names
+                .entry(impersonation::skeleton(name))
+                .or_default()
+                .insert(name.to_string());
+        }
+        self.records.push(record);
+    }
+}
+
+/// How much came in over a period, for the digest.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Volumes {
+    pub(crate) chat_lines: u64,
+    pub(crate) emails: u64,
+    pub(crate) namereqs_received: u64,
+    pub(crate) namereqs_approved: u64,
+    pub(crate) namereqs_denied: u64,
+}
+
+/// What the archive knows about the player behind a name request.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct NameRequestContext {
+    /// Requests from the same player before this one
+    pub(crate) earlier_requests: usize,
+    /// Decisions on the player's earlier requests, oldest first
+    pub(crate) decisions: Vec<(NameRequest, Decision)>,
+    /// Names other players have used that look like the requested one,
+    /// for [`impersonation::check`]
+    pub(crate) similar_names: Vec<String>,
+}
+impl NameRequestContext {
+    fn names_decided(&self, decision: Decision) -> Vec<&str> {
+        self.decisions
+            .iter()
+            .filter(|(_, d)| *d == decision)
+            .map(|(namereq, _)| namereq.requested_name.as_str())
+            .collect()
+    }
+
+    /// The last name approved for the player, which is probably what they're called now.
+    pub(crate) fn current_name(&self) -> Option<&str> {
+        self.names_decided(Decision::Approved).last().copied()
+    }
+
+    /// Lines to add under the name request message.
+    pub(crate) fn lines(&self) -> Vec<String> {
+        let list = |names: &[&str]| {
+            let mut names: Vec<String> = names
+                .iter()
+                .rev()
+                .take(MAX_CONTEXT_NAMES)
+                .map(|name| format!("**{}**", name))
+                .collect();
+            if names.len() == MAX_CONTEXT_NAMES {
+                names.push("...".to_string());
+            }
+            names.join(", ")
+        };
+
+        let approved = self.names_decided(Decision::Approved);
+        let denied = self.names_decided(Decision::Denied);
+        let mut lines = Vec::new();
+        if self.earlier_requests == 0 && self.decisions.is_empty() {
+            lines.push("- No earlier requests from this player in the archive".to_string());
+        } else {
+            lines.push(format!(
+                "- Earlier requests: **{}** ({} approved, {} denied)",
+                self.earlier_requests,
+                approved.len(),
+                denied.len()
+            ));
+        }
+        if let Some((current, previous)) = approved.split_last() {
+            lines.push(format!("- Probably called **{}** now", current));
+            if !previous.is_empty() {
+                lines.push(format!("- Previous names: {}", list(previous)));
+            }
+        }
+        if !denied.is_empty() {
+            lines.push(format!("- Denied before: {}", list(&denied)));
+        }
+        lines
+    }
+}
+
+/// Work for the writer thread, which carries it out in order.
+#[derive(Debug)]
+enum WriteOp {
+    Append(Arc<Record>),
+    /// Replaces the file with just these records, after pruning
+    Rewrite(Vec<Arc<Record>>),
+}
+
+fn append(file: &mut BufWriter<File>, record: &Record) -> Result<()> {
+    serde_json::to_writer(&mut *file, record)?;
+    file.write_all(b"\n")?;
+    Ok(())
+}
+
+fn open_for_append(path: &Path) -> Result<BufWriter<File>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Couldn't open archive {}: {}", path.display(), e))?;
+    Ok(BufWriter::new(file))
+}
+
+/// Writes the records to a new file and swaps it in, so a crash halfway
+/// through leaves the old file as it was.
+fn rewrite(path: &Path, records: &[Arc<Record>]) -> Result<BufWriter<File>> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+    let mut temp = BufWriter::new(File::create(&temp_path)?);
+    for record in records {
+        append(&mut temp, record)?;
+    }
+    temp.flush()?;
+    drop(temp);
+    std::fs::rename(&temp_path, path)?;
+    open_for_append(path)
+}
+
+fn run_writer(path: PathBuf, mut file: BufWriter<File>, ops: mpsc::Receiver<WriteOp>) {
+    while let Ok(op) = ops.recv() {
+        // write everything that's queued up, then flush once
+        let mut next = Some(op);
+        while let Some(op) = next.take().or_else(|| ops.try_recv().ok()) {
+            let res = match op {
+                WriteOp::Append(record) => append(&mut file, &record),
+                WriteOp::Rewrite(records) => rewrite(&path, &records).map(|new| file = new),
+            };
+            if let Err(e) = res {
+                println!("Error while writing archive {}: {}", path.display(), e);
+            }
+        }
+        if let Err(e) = file.flush() {
+            println!("Error while writing archive {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Archive {
+    path: PathBuf,
+    /// Records older than this are dropped; `None` keeps everything
+    retention: Option<Duration>,
+    writer: Mutex<Option<(mpsc::Sender<WriteOp>, JoinHandle<()>)>>,
+    index: RwLock<Index>,
+    /// Held while a prune is being built, so two can't overlap
+    pruning: Mutex<()>,
+}
+impl Archive {
+    /// Opens the archive at `path`, creating it if needed and indexing what's already
+    /// there that's newer than `retention`.
+    pub(crate) fn open(path: &str, retention: Option<Duration>) -> Result<Self> {
+        let cutoff =
+            retention.map(|retention| now_ms().saturating_sub(retention.as_millis() as u64));
+        let mut index = Index::default();
+        let mut num_bad = 0;
+        let mut num_expired = 0;
+        if let Ok(file) = File::open(path) {
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str::<Record>(&line) {
+                    Ok(record) if cutoff.is_some_and(|cutoff| record.at_ms < cutoff) => {
+                        num_expired += 1
+                    }
+                    Ok(record) => index.insert(Arc::new(record)),
+                    Err(_) => num_bad += 1,
+                }
+            }
+        }
+        if num_bad > 0 {
+            println!("Skipped {} unreadable archive entries in {}", num_bad, path);
+        }
+
+        let path = PathBuf::from(path);
+        let file = open_for_append(&path)?;
+        let (ops, received) = mpsc::channel();
+        if num_expired > 0 {
+            println!(
+                "Dropping {} archive entries older than the retention period",
+                num_expired
+            );
+            let _ = ops.send(WriteOp::Rewrite(index.records.clone()));
+        }
+        let writer_path = path.clone();
+        let handle = std::thread::Builder::new()
+            .name("archive writer".to_string())
+            .spawn(move || run_writer(writer_path, file, received))?;
+        Ok(Self {
+            path,
+            retention,
+            writer: Mutex::new(Some((ops, handle))),
+            index: RwLock::new(index),
+            pruning: Mutex::new(()),
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn retention(&self) -> Option<Duration> {
+        self.retention
+    }
+
+    /// Writes out anything still queued and stops taking new records.
+    /// Blocks until the writes are done.
+    pub(crate) fn close(&self) {
+        let writer = self.writer.lock().unwrap().take();
+        if let Some((ops, handle)) = writer {
+            drop(ops);
+            if handle.join().is_err() {
+                println!("Archive writer for {} panicked", self.path.display());
+            }
+        }
+    }
+
+    /// Drops the records that are older than the retention period as of `now`,
+    /// returning how many there were.
+    pub(crate) fn prune(&self, now: SystemTime) -> Result<usize> {
+        let Some(retention) = self.retention else {
+            return Ok(0);
+        };
+        let cutoff = now
+            .checked_sub(retention)
+            .and_then(|cutoff| cutoff.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let _pruning = self.pruning.lock().unwrap();
+        // indexing is the slow part, so it's done without holding the index lock
+        let (num_expired, kept) = {
+            let index = self.index.read().unwrap();
+            // records are kept in the order they came in
+            let num_expired = index
+                .records
+                .partition_point(|record| record.at_ms < cutoff);
+            if num_expired == 0 {
+                return Ok(0);
+            }
+            (num_expired, index.records[num_expired..].to_vec())
+        };
+        let num_kept = kept.len();
+        let mut pruned = Index::default();
+        for record in kept {
+            pruned.insert(record);
+        }
+
+        let mut index = self.index.write().unwrap();
+        // nothing else removes records, so anything past what was kept came in since
+        for record in &index.records[num_expired + num_kept..] {
+            pruned.insert(record.clone());
+        }
+        self.write(WriteOp::Rewrite(pruned.records.clone()))?;
+        *index = pruned;
+        Ok(num_expired)
+    }
+
+    fn write(&self, op: WriteOp) -> Result<()> {
+        let writer = self.writer.lock().unwrap();
+        let Some((ops, _)) = writer.as_ref() else {
+            return Err("The archive is closed".into());
+        };
+        ops.send(op)
+            .map_err(|_| "The archive writer has stopped".into())
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.index.read().unwrap().records.len()
+    }
+
+    /// Stores an event that just came in.
+    /// Stores an event that came in at `at`.
+    pub(crate) fn record(
+        &self,
+        event: Event,
+        message: Option<MessageRef>,
+        at: SystemTime,
+    ) -> Result<()> {
+        self.insert(Record {
+            at_ms: to_ms(at),
+            event,
+            message,
+            decision: None,
+        })
+    }
+
+    /// Stores a moderator's decision on a name request.
+    pub(crate) fn record_decision(&self, namereq: &NameRequest, decision: Decision) -> Result<()> {
+        self.insert(Record {
+            at_ms: now_ms(),
+            event: Event::NameRequest(namereq.clone()),
+            message: None,
+            decision: Some(decision),
+        })
+    }
+
+    fn insert(&self, record: Record) -> Result<()> {
+        // queued under the index lock so the file ends up in the same order as the index
+        let record = Arc::new(record);
+        let mut index = self.index.write().unwrap();
+        self.write(WriteOp::Append(record.clone()))?;
+        index.insert(record);
+        Ok(())
+    }
+
+    /// Everything matching `query`, newest first.
+    pub(crate) fn search(&self, query: &Query) -> Vec<Record> {
+        let index = self.index.read().unwrap();
+        let positions: Box<dyn Iterator<Item = usize>> = match &query.player {
+            Some(player) => match index.by_sender.get(&player.to_lowercase()) {
+                Some(positions) => Box::new(positions.iter().rev().copied()),
+                None => return vec![],
+            },
+            None => Box::new((0..index.records.len()).rev()),
+        };
+        positions
+            .map(|i| &*index.records[i])
+            .filter(|record| query.matches(record))
+            .cloned()
+            .collect()
+    }
+
+    /// Counts what came in between `since` and `until`, and the name requests decided.
+    pub(crate) fn volumes(&self, since: SystemTime, until: SystemTime) -> Volumes {
+        let index = self.index.read().unwrap();
+        let in_period = |record: &&Record| record.time() >= since && record.time() < until;
+        let mut volumes = Volumes::default();
+        for record in index
+            .records
+            .iter()
+            .map(|record| &**record)
+            .filter(in_period)
+        {
+            match (&record.event, record.decision) {
+                (Event::Chat(_), _) => volumes.chat_lines += 1,
+                (Event::Email(_), _) => volumes.emails += 1,
+                (Event::NameRequest(_), None) => volumes.namereqs_received += 1,
+                _ => {}
+            }
+        }
+        let decisions = index.decisions.iter().map(|i| &*index.records[*i]);
+        for record in decisions.filter(in_period) {
+            match record.decision {
+                Some(Decision::Approved) => volumes.namereqs_approved += 1,
+                Some(Decision::Denied) => volumes.namereqs_denied += 1,
+                None => {}
+            }
+        }
+        volumes
+    }
+
+    /// When the bot last saw `namereq` come in, if it's in the archive.
+    pub(crate) fn name_request_seen(&self, namereq: &NameRequest) -> Option<SystemTime> {
+        let index = self.index.read().unwrap();
+        index
+            .name_requests
+            .iter()
+            .rev()
+            .map(|i| &index.records[*i])
+            .find(|record| matches!(&record.event, Event::NameRequest(n) if n == namereq))
+            .map(|record| record.time())
+    }
+
+    /// The message `namereq` was last relayed as, if it's in the archive.
+    pub(crate) fn name_request_message(&self, namereq: &NameRequest) -> Option<MessageRef> {
+        let index = self.index.read().unwrap();
+        index
+            .name_requests
+            .iter()
+            .rev()
+            .map(|i| &index.records[*i])
+            .find(|record| matches!(&record.event, Event::NameRequest(n) if n == namereq))
+            .and_then(|record| record.message)
+    }
+
+    /// What's known about the player who made `namereq`, from before it came in.
+    pub(crate) fn name_request_context(&self, namereq: &NameRequest) -> NameRequestContext {
+        let index = self.index.read().unwrap();
+        let from_player = |i: &usize| match &index.records[*i].event {
+            Event::NameRequest(earlier) if earlier.player_uid == namereq.player_uid => {
+                Some((earlier, index.records[*i].decision))
+            }
+            _ => None,
+        };
+        let earlier_requests = index.name_requests.iter().filter_map(from_player).count();
+        let decisions = index
+            .decisions
+            .iter()
+            .filter_map(from_player)
+            .filter_map(|(earlier, decision)| Some((earlier.clone(), decision?)))
+            .collect();
+        let mut context = NameRequestContext {
+            earlier_requests,
+            decisions,
+            similar_names: vec![],
+        };
+
+        let own_name = context.current_name().map(|name| name.to_lowercase());
+        if let Some(names) = index
+            .names
+            .get(&impersonation::skeleton(&namereq.requested_name))
+        {
+            context.similar_names = names
+                .iter()
+                .filter(|name| Some(name.to_lowercase()) != own_name)
+                .cloned()
+                .collect();
+        }
+        context
+    }
+
+    /// The last `count` chat lines, sent emails and name requests from `player`, oldest first.
+    /// Name requests only carry a UID, so they match on that or on the name requested.
+    pub(crate) fn history(
+        &self,
+        player: &str,
+        since: Option<SystemTime>,
+        count: usize,
+    ) -> Vec<Record> {
+        let index = self.index.read().unwrap();
+        let mut positions = index
+            .by_sender
+            .get(&player.to_lowercase())
+            .cloned()
+            .unwrap_or_default();
+        positions.extend(
+            index
+                .name_requests
+                .iter()
+                .copied()
+                .filter(|i| is_name_request_from(&index.records[*i], player)),
+        );
+        positions.sort_unstable();
+
+        let mut records: Vec<Record> = positions
+            .iter()
+            .rev()
+            .map(|i| &*index.records[*i])
+            .filter(|record| !matches!(record.event, Event::Broadcast(_)))
+            .take_while(|record| since.is_none_or(|since| record.time() >= since))
+            .take(count)
+            .cloned()
+            .collect();
+        records.reverse();
+        records
+    }
+}
+
+impl Drop for Archive {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Drops records past the retention period every so often, for as long as the bot runs.
+pub(crate) async fn prune_periodically(globals: Arc<Globals>) {
+    if globals.archive.retention().is_none() {
+        return;
+    }
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let globals = globals.clone();
+        // rebuilding the index takes a while for a big archive
+        let pruned =
+            tokio::task::spawn_blocking(move || globals.archive.prune(SystemTime::now())).await;
+        match pruned {
+            Ok(Ok(0)) => {}
+            Ok(Ok(n)) => println!("Dropped {} archive entries past the retention period", n),
+            Ok(Err(e)) => println!("Error while pruning archive: {}", e),
+            Err(e) => println!("Error while pruning archive: {}", e),
+        }
+    }
+}
+
+fn is_name_request_from(record: &Record, player: &str) -> bool {
+    let Event::NameRequest(namereq) = &record.event else {
+        return false;
+    };
+    player.parse() == Ok(namereq.player_uid) || namereq.requested_name.eq_ignore_ascii_case(player)
+}
+
+fn now_ms() -> u64 {
+    to_ms(SystemTime::now())
+}
+
+fn to_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use poise::serenity_prelude::{ChannelId, MessageId};
+
+    use super::*;
+    use crate::{
+        events::{ChatEvent, EmailEvent},
+        testing,
+    };
+
+    fn chat(kind: &str, from: &str, to: Option<&str>, message: &str) -> Event {
+        Event::Chat(ChatEvent {
+            kind: kind.to_string(),
+            from: from.to_string(),
+            to: to.map(|t| t.to_string()),
+            message: message.to_string(),
+        })
+    }
+
+    fn record(at_secs: u64, event: Event) -> Record {
+        Record {
+            at_ms: at_secs * 1000,
+            event,
+            message: None,
+            decision: None,
+        }
+    }
+
+    fn archive() -> (Archive, PathBuf) {
+        let path = testing::temp_path("archive");
+        let archive = Archive::open(path.to_str().unwrap(), None).unwrap();
+        archive
+            .insert(record(100, chat("FreeChat", "Alice", None, "hello there")))
+            .unwrap();
+        archive
+            .insert(record(200, chat("BuddyChat", "alice", Some("Bob"), "psst")))
+            .unwrap();
+        archive
+            .insert(record(
+                300,
+                Event::Email(EmailEvent {
+                    from: "Bob".to_string(),
+                    to: "Alice".to_string(),
+                    subject: Some("Trade".to_string()),
+                    body: vec!["Want my Hello Kitty nano?".to_string()],
+                }),
+            ))
+            .unwrap();
+        (archive, path)
+    }
+
+    fn times(records: &[Record]) -> Vec<u64> {
+        records.iter().map(|r| r.at_ms / 1000).collect()
+    }
+
+    #[test]
+    fn search_filters() {
+        let (archive, path) = archive();
+        let search = |query: Query| times(&archive.search(&query));
+
+        assert_eq!(search(Query::default()), [300, 200, 100]);
+        let player = Some("ALICE".to_string());
+        assert_eq!(
+            search(Query {
+                player,
+                ..Default::default()
+            }),
+            [200, 100]
+        );
+        let recipient = Some("bob".to_string());
+        assert_eq!(
+            search(Query {
+                recipient,
+                ..Default::default()
+            }),
+            [200]
+        );
+        let involving = Some("bob".to_string());
+        assert_eq!(
+            search(Query {
+                involving,
+                ..Default::default()
+            }),
+            [300, 200]
+        );
+        let kind = Some(Kind::Email);
+        assert_eq!(
+            search(Query {
+                kind,
+                ..Default::default()
+            }),
+            [300]
+        );
+        let kind = Some(Kind::FreeChat);
+        assert_eq!(
+            search(Query {
+                kind,
+                ..Default::default()
+            }),
+            [100]
+        );
+        let text = Some("hello".to_string());
+        assert_eq!(
+            search(Query {
+                text,
+                ..Default::default()
+            }),
+            [300, 100]
+        );
+        let since = Some(UNIX_EPOCH + Duration::from_secs(150));
+        let until = Some(UNIX_EPOCH + Duration::from_secs(250));
+        assert_eq!(
+            search(Query {
+                since,
+                until,
+                ..Default::default()
+            }),
+            [200]
+        );
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn player_history() {
+        let (archive, path) = archive();
+        archive
+            .insert(record(
+                400,
+                Event::NameRequest(NameRequest {
+                    player_uid: 7,
+                    requested_name: "Alicia".to_string(),
+                }),
+            ))
+            .unwrap();
+        archive
+            .insert(record(500, chat("FreeChat", "Alice", None, "bye")))
+            .unwrap();
+
+        assert_eq!(times(&archive.history("alice", None, 10)), [100, 200, 500]);
+        assert_eq!(times(&archive.history("Alice", None, 2)), [200, 500]);
+        let since = Some(UNIX_EPOCH + Duration::from_secs(150));
+        assert_eq!(times(&archive.history("Alice", since, 10)), [200, 500]);
+        // emails count for the sender only
+        assert_eq!(times(&archive.history("Bob", None, 10)), [300]);
+        assert_eq!(times(&archive.history("7", None, 10)), [400]);
+        assert_eq!(times(&archive.history("alicia", None, 10)), [400]);
+        assert!(archive.history("Nobody", None, 10).is_empty());
+
+        let namereq = NameRequest {
+            player_uid: 7,
+            requested_name: "Alicia".to_string(),
+        };
+        let seen = archive.name_request_seen(&namereq);
+        assert_eq!(seen, Some(UNIX_EPOCH + Duration::from_secs(400)));
+        let namereq = NameRequest {
+            player_uid: 8,
+            ..namereq
+        };
+        assert_eq!(archive.name_request_seen(&namereq), None);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn name_request_context() {
+        let (archive, path) = archive();
+        let namereq = |player_uid, name: &str| NameRequest {
+            player_uid,
+            requested_name: name.to_string(),
+        };
+        let context = archive.name_request_context(&namereq(7, "B.0.B"));
+        assert_eq!(context.similar_names, ["Bob"]);
+        assert_eq!(
+            context.lines(),
+            ["- No earlier requests from this player in the archive"]
+        );
+
+        for (name, decision) in [
+            ("Xx_Bob_xX", Decision::Denied),
+            ("Robert", Decision::Approved),
+            ("Bob", Decision::Approved),
+        ] {
+            let earlier = namereq(7, name);
+            archive
+                .record(Event::NameRequest(earlier.clone()), None, SystemTime::now())
+                .unwrap();
+            archive.record_decision(&earlier, decision).unwrap();
+        }
+        let message = MessageRef {
+            channel: ChannelId::new(testing::NAME_APPROVALS_CHANNEL),
+            id: MessageId::new(56),
+        };
+        archive
+            .record(
+                Event::NameRequest(namereq(8, "Robert")),
+                Some(message),
+                SystemTime::now(),
+            )
+            .unwrap();
+        assert_eq!(
+            archive.name_request_message(&namereq(8, "Robert")),
+            Some(message)
+        );
+        assert_eq!(archive.name_request_message(&namereq(7, "Bob")), None);
+
+        // the player's own name doesn't count as someone else's
+        let context = archive.name_request_context(&namereq(7, "bob"));
+        assert_eq!(context.current_name(), Some("Bob"));
+        assert!(context.similar_names.is_empty());
+        assert_eq!(
+            context.lines(),
+            [
+                "- Earlier requests: **3** (2 approved, 1 denied)",
+                "- Probably called **Bob** now",
+                "- Previous names: **Robert**",
+                "- Denied before: **Xx_Bob_xX**"
+            ]
+        );
+
+        // decisions stay out of searches and histories
+        assert_eq!(archive.search(&Query::default()).len(), 3);
+        assert_eq!(archive.history("7", None, 10).len(), 3);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn volumes() {
+        let (archive, path) = archive();
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        let bob = Event::NameRequest(NameRequest {
+            player_uid: 7,
+            requested_name: "Bob".to_string(),
+        });
+        archive.insert(record(400, bob.clone())).unwrap();
+        archive
+            .insert(Record {
+                decision: Some(Decision::Denied),
+                ..record(500, bob)
+            })
+            .unwrap();
+
+        assert_eq!(
+            archive.volumes(at(150), at(1000)),
+            Volumes {
+                chat_lines: 1,
+                emails: 1,
+                namereqs_received: 1,
+                namereqs_approved: 0,
+                namereqs_denied: 1,
+            }
+        );
+        let before = archive.volumes(at(0), at(300));
+        assert_eq!((before.chat_lines, before.emails), (2, 0));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn old_records_are_pruned() {
+        let path = testing::temp_path("archive");
+        let day = Duration::from_secs(24 * 60 * 60);
+        let archive = Archive::open(path.to_str().unwrap(), Some(day)).unwrap();
+        archive
+            .insert(record(100, chat("FreeChat", "Alice", None, "old news")))
+            .unwrap();
+        archive
+            .record(
+                chat("FreeChat", "Bob", None, "fresh"),
+                None,
+                SystemTime::now(),
+            )
+            .unwrap();
+
+        assert_eq!(archive.prune(SystemTime::now()).unwrap(), 1);
+        assert_eq!(archive.prune(SystemTime::now()).unwrap(), 0);
+        archive
+            .record(
+                chat("FreeChat", "Carol", None, "fresher"),
+                None,
+                SystemTime::now(),
+            )
+            .unwrap();
+        let query = Query {
+            player: Some("alice".to_string()),
+            ..Default::default()
+        };
+        assert!(archive.search(&query).is_empty());
+        drop(archive);
+
+        // the file was rewritten without the old record, and kept what came after
+        let archive = Archive::open(path.to_str().unwrap(), None).unwrap();
+        let found = archive.search(&Query::default());
+        let senders: Vec<_> = found.iter().filter_map(|r| r.sender()).collect();
+        assert_eq!(senders, ["Carol", "Bob"]);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn archive_survives_restart() {
+        let (archive, path) = archive();
+        let message = MessageRef {
+            channel: ChannelId::new(testing::LOG_CHANNEL),
+            id: MessageId::new(55),
+        };
+        archive
+            .record(
+                chat("GroupChat", "Carol", None, "gg"),
+                Some(message),
+                SystemTime::now(),
+            )
+            .unwrap();
+        drop(archive);
+
+        let archive = Archive::open(path.to_str().unwrap(), None).unwrap();
+        assert_eq!(archive.len(), 4);
+        let query = Query {
+            player: Some("carol".to_string()),
+            ..Default::default()
+        };
+        let found = archive.search(&query);
+        assert_eq!(found.len(), 1);
+        assert!(found[0]
+            .format(GuildId::new(1))
+            .ends_with("[GroupChat] Carol: gg [jump](https://discord.com/channels/1/1002/55)"));
+        let _ = std::fs::remove_file(path);
+    }
+}
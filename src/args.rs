@@ -0,0 +1,70 @@
+use crate::Result;
+
+pub(crate) const USAGE: &str = "Usage: computress [path to config.json] [--record <dir>] [--replay <file> [--replay-speed <factor>]] [--dry-run [--dry-run-output <file>]]";
+
+/// Command line options
+#[derive(Debug)]
+pub(crate) struct Args {
+    pub(crate) config_path: String,
+    /// Directory to record monitor notifications into
+    pub(crate) record_dir: Option<String>,
+    /// Recording to play back instead of connecting to the monitor
+    pub(crate) replay_path: Option<String>,
+    /// How many times faster than real time to play back; 0 means no delays at all
+    pub(crate) replay_speed: f64,
+    /// Run without Discord, only writing out what would be posted
+    pub(crate) dry_run: bool,
+    /// File to write dry-run output to instead of stdout
+    pub(crate) dry_run_output: Option<String>,
+}
+impl Args {
+    pub(crate) fn parse(mut args: impl Iterator<Item = String>) -> Result<Args> {
+        let mut config_path = None;
+        let mut record_dir = None;
+        let mut replay_path = None;
+        let mut replay_speed = None;
+        let mut dry_run = false;
+        let mut dry_run_output = None;
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+            match arg.as_str() {
+                "--record" => record_dir = Some(value()?),
+                "--replay" => replay_path = Some(value()?),
+                "--dry-run" => dry_run = true,
+                "--dry-run-output" => dry_run_output = Some(value()?),
+                "--replay-speed" => {
+                    let speed = value()?;
+                    let speed = speed
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|s| *s >= 0.0)
+                        .ok_or(format!("Bad replay speed: {}", speed))?;
+                    replay_speed = Some(speed);
+                }
+                _ if arg.starts_with("--") => return Err(format!("Unknown option: {}", arg).into()),
+                _ if config_path.is_none() => config_path = Some(arg),
+                _ => return Err(format!("Unexpected argument: {}", arg).into()),
+            }
+        }
+
+        if replay_path.is_some() && record_dir.is_some() {
+            return Err("--record and --replay can't be used together".into());
+        }
+        if replay_speed.is_some() && replay_path.is_none() {
+            return Err("--replay-speed needs --replay".into());
+        }
+        if dry_run_output.is_some() && !dry_run {
+            return Err("--dry-run-output needs --dry-run".into());
+        }
+
+        Ok(Args {
+            config_path: config_path.unwrap_or("config.json".to_string()),
+            record_dir,
+            replay_path,
+            replay_speed: replay_speed.unwrap_or(1.0),
+            dry_run,
+            dry_run_output,
+        })
+    }
+}
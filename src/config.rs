@@ -0,0 +1,661 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use poise::serenity_prelude::{ChannelId, RoleId};
+use serde::Deserialize;
+
+use crate::{
+    endpoint::OfapiOptions,
+    impersonation,
+    permissions::{self, Permission, PermissionRule},
+    Result,
+};
+
+const DEFAULT_MONITOR_RECONNECT_MIN_SECS: u64 = 1;
+const DEFAULT_MONITOR_RECONNECT_MAX_SECS: u64 = 60;
+const DEFAULT_ARCHIVE_PATH: &str = "archive.jsonl";
+const DEFAULT_ARCHIVE_RETENTION_DAYS: u64 = 90;
+const DEFAULT_REMINDER_STALE_AFTER_HOURS: u64 = 24;
+const DEFAULT_REMINDER_EVERY_HOURS: u64 = 6;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Config {
+    pub(crate) guild_id: u64,
+    mod_role_id: Option<u64>,
+    mod_role_ids: Option<Vec<u64>>,
+    pub(crate) mod_channel_id: u64,
+    pub(crate) log_channel_id: u64,
+    pub(crate) name_approvals_channel_id: u64,
+    pub(crate) monitor_address: String,
+    monitor_reconnect_min_secs: Option<u64>,
+    monitor_reconnect_max_secs: Option<u64>,
+    archive_path: Option<String>,
+    archive_retention_days: Option<u64>,
+    pub(crate) ofapi_endpoint: String,
+    pub(crate) ofapi_timeout_secs: Option<u64>,
+    pub(crate) ofapi_connect_timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub(crate) ofapi_ca_certs: Vec<String>,
+    pub(crate) ofapi_proxy: Option<String>,
+    pub(crate) ofapi_token: Option<String>,
+    pub(crate) ofapi_username: Option<String>,
+    pub(crate) ofapi_password: Option<String>,
+    pub(crate) ofapi_login_path: Option<String>,
+    #[serde(default)]
+    permissions: HashMap<String, PermissionRule>,
+    #[serde(default)]
+    message_formats: HashMap<u64, FormatRule>,
+    digest: Option<DigestConfig>,
+    namereq_reminders: Option<RemindersConfig>,
+    #[serde(default)]
+    impersonation: Impersonation,
+}
+impl Config {
+    pub(crate) fn load(path: &str) -> Result<Config> {
+        let Ok(contents) = std::fs::read_to_string(path) else {
+            return Err(format!("Config file missing: {}", path).into());
+        };
+        let config: Config = match serde_json::from_str(&contents) {
+            Ok(config) => config,
+            Err(e) => return Err(format!("Error while parsing {}: {}", path, e).into()),
+        };
+        if let Some(e) = config.validate() {
+            return Err(format!("Invalid config: {}", e).into());
+        }
+        if let Some(e) = permissions::validate_rules(&config.permissions) {
+            return Err(format!("Invalid config: {}", e).into());
+        }
+        if let Err(e) = OfapiOptions::from_config(&config) {
+            return Err(format!("Invalid config: {}", e).into());
+        }
+        Ok(config)
+    }
+
+    fn validate(&self) -> Option<&str> {
+        if self.guild_id == 0 {
+            return Some("guild_id must be set");
+        }
+        if self.mod_role_id.is_none_or(|id| id == 0)
+            && self.mod_role_ids.as_ref().is_none_or(|ids| ids.is_empty())
+        {
+            return Some("Either mod_role_id or mod_role_ids must be set");
+        }
+        if self.mod_channel_id == 0 {
+            return Some("mod_channel_id must be set");
+        }
+        if self.message_formats.contains_key(&0) {
+            return Some("message_formats can't use channel 0");
+        }
+        if self.monitor_reconnect_min_secs == Some(0) {
+            return Some("monitor_reconnect_min_secs must be at least 1");
+        }
+        if let Some(digest) = &self.digest {
+            if let Err(e) = DigestSchedule::from_config(digest) {
+                return Some(e);
+            }
+        }
+        if let Some(reminders) = &self.namereq_reminders {
+            if let Err(e) = Reminders::from_config(reminders) {
+                return Some(e);
+            }
+        }
+        if let Some(e) = self.impersonation.validate() {
+            return Some(e);
+        }
+        let backoff = self.get_monitor_backoff();
+        if backoff.max < backoff.min {
+            return Some(
+                "monitor_reconnect_max_secs can't be less than monitor_reconnect_min_secs",
+            );
+        }
+        None
+    }
+
+    fn get_monitor_backoff(&self) -> Backoff {
+        let min = self
+            .monitor_reconnect_min_secs
+            .unwrap_or(DEFAULT_MONITOR_RECONNECT_MIN_SECS);
+        let max = self
+            .monitor_reconnect_max_secs
+            .unwrap_or(DEFAULT_MONITOR_RECONNECT_MAX_SECS.max(min));
+        Backoff {
+            min: Duration::from_secs(min),
+            max: Duration::from_secs(max),
+        }
+    }
+
+    pub(crate) fn get_archive_path(&self) -> &str {
+        self.archive_path.as_deref().unwrap_or(DEFAULT_ARCHIVE_PATH)
+    }
+
+    /// How long archived events are kept, or `None` to keep them forever.
+    pub(crate) fn get_archive_retention(&self) -> Option<Duration> {
+        match self
+            .archive_retention_days
+            .unwrap_or(DEFAULT_ARCHIVE_RETENTION_DAYS)
+        {
+            0 => None,
+            days => Some(Duration::from_secs(days * 24 * 60 * 60)),
+        }
+    }
+
+    fn get_mod_role_ids(&self) -> HashSet<RoleId> {
+        let mut set = HashSet::new();
+        if let Some(id) = self.mod_role_id {
+            set.insert(RoleId::new(id));
+        }
+        if let Some(ids) = &self.mod_role_ids {
+            for id in ids {
+                set.insert(RoleId::new(*id));
+            }
+        }
+        set
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum MessageFormat {
+    /// One line of plain text per event
+    #[default]
+    Text,
+    /// A colour-coded embed per event
+    Embed,
+}
+
+/// How relayed events look in one channel.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+pub(crate) struct ChannelFormats {
+    #[serde(default)]
+    pub(crate) chat: MessageFormat,
+    #[serde(default)]
+    pub(crate) email: MessageFormat,
+    #[serde(default)]
+    pub(crate) broadcast: MessageFormat,
+}
+
+/// Either one format for everything or one per event type.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum FormatRule {
+    All(MessageFormat),
+    PerEvent(ChannelFormats),
+}
+impl From<&FormatRule> for ChannelFormats {
+    fn from(rule: &FormatRule) -> Self {
+        match rule {
+            FormatRule::All(format) => Self {
+                chat: *format,
+                email: *format,
+                broadcast: *format,
+            },
+            FormatRule::PerEvent(formats) => *formats,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum DigestPeriod {
+    #[default]
+    Daily,
+    Weekly,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+#[derive(Debug, Deserialize)]
+struct DigestConfig {
+    /// "HH:MM", in UTC
+    time: String,
+    #[serde(default)]
+    period: DigestPeriod,
+    /// For weekly digests; Monday if not set
+    weekday: Option<Weekday>,
+}
+
+/// When to post the digest.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct DigestSchedule {
+    pub(crate) period: DigestPeriod,
+    weekday: Weekday,
+    /// Seconds after midnight UTC
+    secs_of_day: u64,
+}
+impl DigestSchedule {
+    fn from_config(config: &DigestConfig) -> std::result::Result<Self, &'static str> {
+        let secs_of_day =
+            parse_time_of_day(&config.time).ok_or("digest time must be HH:MM (UTC)")?;
+        if config.period == DigestPeriod::Daily && config.weekday.is_some() {
+            return Err("digest weekday only applies to weekly digests");
+        }
+        Ok(Self {
+            period: config.period,
+            weekday: config.weekday.unwrap_or(Weekday::Monday),
+            secs_of_day,
+        })
+    }
+
+    /// The last time the digest was due, at or before `now`.
+    pub(crate) fn last_due(&self, now: SystemTime) -> SystemTime {
+        const DAY: u64 = 24 * 60 * 60;
+        let now = now
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let mut due = now - now % DAY + self.secs_of_day;
+        if due > now {
+            due = due.saturating_sub(DAY);
+        }
+        if self.period == DigestPeriod::Weekly {
+            // 1970-01-01 was a Thursday
+            while (due / DAY + 3) % 7 != self.weekday as u64 {
+                due = due.saturating_sub(DAY);
+            }
+        }
+        UNIX_EPOCH + Duration::from_secs(due)
+    }
+}
+impl Display for DigestSchedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let time = format_time_of_day(self.secs_of_day);
+        match self.period {
+            DigestPeriod::Daily => write!(f, "daily at {}", time),
+            DigestPeriod::Weekly => write!(f, "weekly on {:?} at {}", self.weekday, time),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct QuietHoursConfig {
+    /// "HH:MM", in UTC
+    start: String,
+    /// "HH:MM", in UTC
+    end: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RemindersConfig {
+    role_id: u64,
+    stale_after_hours: Option<u64>,
+    every_hours: Option<u64>,
+    quiet_hours: Option<QuietHoursConfig>,
+}
+
+/// A daily stretch of time when the bot shouldn't ping anyone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct QuietHours {
+    /// Seconds after midnight UTC
+    start: u64,
+    /// Seconds after midnight UTC; before `start` if the quiet hours span midnight
+    end: u64,
+}
+impl QuietHours {
+    pub(crate) fn contains(&self, time: SystemTime) -> bool {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() % (24 * 60 * 60))
+            .unwrap_or(0);
+        if self.start <= self.end {
+            (self.start..self.end).contains(&secs)
+        } else {
+            secs >= self.start || secs < self.end
+        }
+    }
+}
+
+/// When to remind moderators about name requests that have been waiting too long.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Reminders {
+    pub(crate) role: RoleId,
+    pub(crate) stale_after: Duration,
+    pub(crate) every: Duration,
+    pub(crate) quiet_hours: Option<QuietHours>,
+}
+impl Reminders {
+    fn from_config(config: &RemindersConfig) -> std::result::Result<Self, &'static str> {
+        if config.role_id == 0 {
+            return Err("namereq_reminders role_id must be set");
+        }
+        let stale_after_hours = config
+            .stale_after_hours
+            .unwrap_or(DEFAULT_REMINDER_STALE_AFTER_HOURS);
+        let every_hours = config.every_hours.unwrap_or(DEFAULT_REMINDER_EVERY_HOURS);
+        if stale_after_hours == 0 || every_hours == 0 {
+            return Err("namereq_reminders stale_after_hours and every_hours must be at least 1");
+        }
+        let quiet_hours = match &config.quiet_hours {
+            Some(quiet) => {
+                let invalid = "namereq_reminders quiet_hours must be HH:MM (UTC)";
+                let start = parse_time_of_day(&quiet.start).ok_or(invalid)?;
+                let end = parse_time_of_day(&quiet.end).ok_or(invalid)?;
+                Some(QuietHours { start, end })
+            }
+            None => None,
+        };
+        Ok(Self {
+            role: RoleId::new(config.role_id),
+            stale_after: Duration::from_secs(stale_after_hours * 60 * 60),
+            every: Duration::from_secs(every_hours * 60 * 60),
+            quiet_hours,
+        })
+    }
+}
+impl Display for Reminders {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "every {}h for requests older than {}h, pinging role {}",
+            self.every.as_secs() / 3600,
+            self.stale_after.as_secs() / 3600,
+            self.role
+        )?;
+        if let Some(quiet) = self.quiet_hours {
+            write!(
+                f,
+                ", quiet from {} to {}",
+                format_time_of_day(quiet.start),
+                format_time_of_day(quiet.end)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Names that requested names shouldn't imitate.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub(crate) struct Impersonation {
+    #[serde(default)]
+    pub(crate) staff_names: Vec<String>,
+    #[serde(default)]
+    pub(crate) protected_names: Vec<String>,
+    /// Deny requests imitating staff or protected names instead of posting them
+    #[serde(default)]
+    pub(crate) auto_deny: bool,
+}
+impl Impersonation {
+    fn validate(&self) -> Option<&'static str> {
+        let names = self.staff_names.iter().chain(&self.protected_names);
+        if names
+            .map(|name| impersonation::skeleton(name))
+            .any(|s| s.is_empty())
+        {
+            return Some("impersonation names must contain letters or numbers");
+        }
+        None
+    }
+}
+impl Display for Impersonation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} staff name(s), {} protected name(s), auto-deny {}",
+            self.staff_names.len(),
+            self.protected_names.len(),
+            if self.auto_deny { "on" } else { "off" }
+        )
+    }
+}
+
+/// Parses "HH:MM" into seconds after midnight.
+fn parse_time_of_day(time: &str) -> Option<u64> {
+    let (hours, minutes) = time.split_once(':')?;
+    let (hours, minutes): (u64, u64) = (hours.parse().ok()?, minutes.parse().ok()?);
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    Some(hours * 60 * 60 + minutes * 60)
+}
+
+fn format_time_of_day(secs: u64) -> String {
+    format!("{:02}:{:02} UTC", secs / 3600, secs / 60 % 60)
+}
+
+/// How long to wait between monitor reconnect attempts.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Backoff {
+    pub(crate) min: Duration,
+    pub(crate) max: Duration,
+}
+impl Backoff {
+    /// The delay after `failures` failures in a row, doubling each time.
+    pub(crate) fn delay(&self, failures: u32) -> Duration {
+        let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+        self.min.saturating_mul(factor).min(self.max)
+    }
+}
+
+/// The parts of the config that can be swapped out while the bot is running.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Settings {
+    pub(crate) mod_roles: HashSet<RoleId>,
+    pub(crate) mod_channel: ChannelId,
+    pub(crate) log_channel: Option<ChannelId>,
+    pub(crate) name_approvals_channel: Option<ChannelId>,
+    pub(crate) permissions: HashMap<String, Permission>,
+    pub(crate) monitor_backoff: Backoff,
+    pub(crate) message_formats: HashMap<ChannelId, ChannelFormats>,
+    pub(crate) digest: Option<DigestSchedule>,
+    pub(crate) namereq_reminders: Option<Reminders>,
+    pub(crate) impersonation: Impersonation,
+}
+impl Settings {
+    pub(crate) fn diff(&self, new: &Settings) -> Vec<String> {
+        let mut changes = Vec::new();
+        if self.mod_roles != new.mod_roles {
+            let mut roles: Vec<String> = new.mod_roles.iter().map(|r| r.to_string()).collect();
+            roles.sort();
+            changes.push(format!("mod roles: {}", roles.join(", ")));
+        }
+        if self.mod_channel != new.mod_channel {
+            changes.push(format!("mod channel: {}", new.mod_channel));
+        }
+        if self.log_channel != new.log_channel {
+            changes.push(format!("log channel: {}", fmt_channel(new.log_channel)));
+        }
+        if self.name_approvals_channel != new.name_approvals_channel {
+            changes.push(format!(
+                "name approvals channel: {}",
+                fmt_channel(new.name_approvals_channel)
+            ));
+        }
+        let mut actions: Vec<&String> = new
+            .permissions
+            .iter()
+            .filter(|(action, p)| self.permissions.get(*action) != Some(*p))
+            .map(|(action, _)| action)
+            .collect();
+        if !actions.is_empty() {
+            actions.sort();
+            let actions: Vec<&str> = actions.iter().map(|a| a.as_str()).collect();
+            changes.push(format!("permissions for: {}", actions.join(", ")));
+        }
+        let mut channels: Vec<String> = new
+            .message_formats
+            .iter()
+            .filter(|(channel, f)| self.message_formats.get(*channel) != Some(*f))
+            .map(|(channel, _)| channel)
+            .chain(
+                self.message_formats
+                    .keys()
+                    .filter(|channel| !new.message_formats.contains_key(*channel)),
+            )
+            .map(|channel| channel.to_string())
+            .collect();
+        if !channels.is_empty() {
+            channels.sort();
+            changes.push(format!("message formats for: {}", channels.join(", ")));
+        }
+        if self.monitor_backoff != new.monitor_backoff {
+            changes.push(format!(
+                "monitor reconnect backoff: {:?} to {:?}",
+                new.monitor_backoff.min, new.monitor_backoff.max
+            ));
+        }
+        if self.digest != new.digest {
+            let digest = match new.digest {
+                Some(digest) => digest.to_string(),
+                None => "disabled".to_string(),
+            };
+            changes.push(format!("digest: {}", digest));
+        }
+        if self.namereq_reminders != new.namereq_reminders {
+            let reminders = match new.namereq_reminders {
+                Some(reminders) => reminders.to_string(),
+                None => "disabled".to_string(),
+            };
+            changes.push(format!("name request reminders: {}", reminders));
+        }
+        if self.impersonation != new.impersonation {
+            changes.push(format!("impersonation check: {}", new.impersonation));
+        }
+        changes
+    }
+
+    /// How events relayed to `channel` should look.
+    pub(crate) fn formats_for(&self, channel: ChannelId) -> ChannelFormats {
+        self.message_formats
+            .get(&channel)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Every role the settings refer to, for checking that they exist.
+    pub(crate) fn get_all_roles(&self) -> HashSet<RoleId> {
+        let mut roles = self.mod_roles.clone();
+        for permission in self.permissions.values() {
+            roles.extend(permission.get_roles());
+        }
+        roles.extend(self.namereq_reminders.map(|r| r.role));
+        roles
+    }
+}
+impl From<&Config> for Settings {
+    fn from(config: &Config) -> Self {
+        let mod_roles = config.get_mod_role_ids();
+        Self {
+            permissions: permissions::resolve(&config.permissions, &mod_roles),
+            mod_roles,
+            mod_channel: ChannelId::new(config.mod_channel_id),
+            log_channel: optional_channel(config.log_channel_id),
+            name_approvals_channel: optional_channel(config.name_approvals_channel_id),
+            monitor_backoff: config.get_monitor_backoff(),
+            message_formats: config
+                .message_formats
+                .iter()
+                .map(|(id, rule)| (ChannelId::new(*id), rule.into()))
+                .collect(),
+            digest: config
+                .digest
+                .as_ref()
+                .and_then(|digest| DigestSchedule::from_config(digest).ok()),
+            namereq_reminders: config
+                .namereq_reminders
+                .as_ref()
+                .and_then(|reminders| Reminders::from_config(reminders).ok()),
+            impersonation: config.impersonation.clone(),
+        }
+    }
+}
+
+fn optional_channel(id: u64) -> Option<ChannelId> {
+    if id != 0 {
+        Some(ChannelId::new(id))
+    } else {
+        None
+    }
+}
+
+fn fmt_channel(channel: Option<impl Display>) -> String {
+    match channel {
+        Some(channel) => channel.to_string(),
+        None => "disabled".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::testing;
+
+    fn diff(overrides: serde_json::Value) -> Vec<String> {
+        testing::settings().diff(&testing::settings_with(overrides))
+    }
+
+    #[test]
+    fn unchanged_settings_have_no_diff() {
+        assert!(diff(json!({})).is_empty());
+        // a default written out is no change
+        assert!(diff(json!({ "monitor_reconnect_min_secs": 1 })).is_empty());
+        // needs a restart, so it isn't one of the settings
+        assert!(diff(json!({ "guild_id": 9, "ofapi_timeout_secs": 30 })).is_empty());
+    }
+
+    #[test]
+    fn changed_settings_are_listed() {
+        assert_eq!(
+            diff(json!({ "mod_role_ids": [4, 3] })),
+            [
+                "mod roles: 3, 4",
+                // the privileged defaults are mods only, so they follow the mod roles
+                "permissions for: diagnose, export, history, monitor, namereq_approve, \
+                 namereq_deny, namereqs, reload, search",
+            ]
+        );
+        assert_eq!(diff(json!({ "mod_channel_id": 7 })), ["mod channel: 7"]);
+        assert_eq!(
+            diff(json!({ "log_channel_id": 0, "name_approvals_channel_id": 8 })),
+            ["log channel: disabled", "name approvals channel: 8"]
+        );
+        assert_eq!(
+            diff(json!({ "permissions": { "reload": { "users": [9] } } })),
+            ["permissions for: reload"]
+        );
+        assert_eq!(
+            diff(json!({ "monitor_reconnect_min_secs": 2, "monitor_reconnect_max_secs": 30 })),
+            ["monitor reconnect backoff: 2s to 30s"]
+        );
+        assert_eq!(
+            diff(json!({ "digest": { "time": "09:00" } })),
+            ["digest: daily at 09:00 UTC"]
+        );
+        assert_eq!(
+            diff(json!({ "namereq_reminders": { "role_id": 5 } })),
+            ["name request reminders: every 6h for requests older than 24h, pinging role 5"]
+        );
+        assert_eq!(
+            diff(json!({ "impersonation": { "protected_names": ["Admin"], "auto_deny": true } })),
+            ["impersonation check: 0 staff name(s), 1 protected name(s), auto-deny on"]
+        );
+    }
+
+    #[test]
+    fn message_format_changes_list_channels() {
+        let old = testing::settings_with(json!({
+            "message_formats": { "1001": "embed", "1002": "embed" }
+        }));
+        let new = testing::settings_with(json!({
+            "message_formats": { "1001": "embed", "1002": { "chat": "text" }, "1003": "embed" }
+        }));
+        assert_eq!(old.diff(&new), ["message formats for: 1002, 1003"]);
+        // dropping a channel's formats is a change too
+        assert_eq!(
+            old.diff(&testing::settings()),
+            ["message formats for: 1001, 1002"]
+        );
+    }
+}
@@ -0,0 +1,415 @@
+//! A periodic summary of what happened, posted to the mod channel.
+
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use poise::serenity_prelude::{Mention, UserId};
+
+use crate::{
+    archive::Volumes,
+    config::{DigestPeriod, DigestSchedule},
+    endpoint,
+    events::Notification,
+    util::{self, TimestampStyle},
+    Globals, Result,
+};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_MODERATORS: usize = 3;
+const MAX_ALERTS: usize = 10;
+
+/// What the bot saw itself during the current digest period. Message and
+/// name request volumes come from the archive instead, so they survive restarts.
+#[derive(Debug)]
+pub(crate) struct Stats {
+    since: SystemTime,
+    peak_players: usize,
+    player_samples: u64,
+    player_total: u64,
+    /// When the server last came online, if it's online now
+    online_since: Option<SystemTime>,
+    uptime: Duration,
+    outages: u32,
+    decisions_by: HashMap<UserId, u32>,
+    alerts: Vec<(SystemTime, String)>,
+}
+impl Stats {
+    pub(crate) fn new(since: SystemTime) -> Self {
+        Self {
+            since,
+            peak_players: 0,
+            player_samples: 0,
+            player_total: 0,
+            online_since: None,
+            uptime: Duration::ZERO,
+            outages: 0,
+            decisions_by: HashMap::new(),
+            alerts: Vec::new(),
+        }
+    }
+
+    pub(crate) fn record_notification(&mut self, notification: &Notification, at: SystemTime) {
+        match notification {
+            Notification::Connected => {
+                self.online_since.get_or_insert(at);
+            }
+            Notification::Disconnected => {
+                if let Some(since) = self.online_since.take() {
+                    self.uptime += at.duration_since(since).unwrap_or_default();
+                    self.outages += 1;
+                    self.record_alert("Lost connection to the monitor", at);
+                }
+            }
+            Notification::Updated { player_count, .. } => {
+                self.online_since.get_or_insert(at);
+                self.peak_players = self.peak_players.max(*player_count);
+                self.player_samples += 1;
+                self.player_total += *player_count as u64;
+            }
+        }
+    }
+
+    pub(crate) fn record_decision(&mut self, by: UserId) {
+        *self.decisions_by.entry(by).or_default() += 1;
+    }
+
+    /// Notes a warning the bot posted, to be listed in the next digest.
+    pub(crate) fn record_alert(&mut self, alert: &str, at: SystemTime) {
+        self.alerts.push((at, alert.to_string()));
+    }
+
+    /// Ends the period at `now`, returning its stats and starting the next one.
+    pub(crate) fn take(&mut self, now: SystemTime) -> Stats {
+        let mut next = Stats::new(now);
+        if let Some(since) = self.online_since {
+            self.uptime += now.duration_since(since).unwrap_or_default();
+            next.online_since = Some(now);
+        }
+        std::mem::replace(self, next)
+    }
+
+    /// The digest message for the period from `since` to `until`, which these stats
+    /// only cover part of if the bot started partway through.
+    pub(crate) fn report(
+        &self,
+        period: DigestPeriod,
+        since: SystemTime,
+        until: SystemTime,
+        volumes: &Volumes,
+        pending_namereqs: Option<usize>,
+    ) -> String {
+        let title = match period {
+            DigestPeriod::Daily => "Daily digest",
+            DigestPeriod::Weekly => "Weekly digest",
+        };
+        let mut lines = vec![format!(
+            "**{}** since {}",
+            title,
+            util::discord_timestamp(since, TimestampStyle::ShortDateTime)
+        )];
+        if self.since > since {
+            lines.push(format!(
+                "Population, uptime and moderators since the bot started {}",
+                util::discord_timestamp(self.since, TimestampStyle::ShortDateTime)
+            ));
+        }
+
+        let average = match self.player_samples {
+            0 => 0.0,
+            n => self.player_total as f64 / n as f64,
+        };
+        lines.push(format!(
+            "Population: peak **{}**, average **{:.1}**",
+            self.peak_players, average
+        ));
+
+        let total = until.duration_since(self.since).unwrap_or_default();
+        let percent = match total.as_secs() {
+            0 => 0.0,
+            secs => 100.0 * self.uptime.as_secs() as f64 / secs as f64,
+        };
+        lines.push(format!(
+            "Server uptime: **{:.1}%** ({}), **{}** outage(s)",
+            percent,
+            util::format_duration(self.uptime),
+            self.outages
+        ));
+        lines.push(format!(
+            "Chat: **{}** lines, emails: **{}**",
+            volumes.chat_lines, volumes.emails
+        ));
+
+        let pending = match pending_namereqs {
+            Some(n) => n.to_string(),
+            None => "unknown".to_string(),
+        };
+        lines.push(format!(
+            "Name requests: **{}** received, **{}** approved, **{}** denied, **{}** pending",
+            volumes.namereqs_received, volumes.namereqs_approved, volumes.namereqs_denied, pending
+        ));
+
+        let mut moderators: Vec<(&UserId, &u32)> = self.decisions_by.iter().collect();
+        moderators.sort_by(|(a_id, a), (b_id, b)| b.cmp(a).then(a_id.cmp(b_id)));
+        if !moderators.is_empty() {
+            let moderators: Vec<String> = moderators
+                .iter()
+                .take(MAX_MODERATORS)
+                .map(|(id, n)| format!("{} ({})", Mention::from(**id), n))
+                .collect();
+            lines.push(format!("Most active moderators: {}", moderators.join(", ")));
+        }
+
+        if self.alerts.is_empty() {
+            lines.push("Alerts: none".to_string());
+        } else {
+            lines.push(format!("Alerts: **{}**", self.alerts.len()));
+            for (at, alert) in self.alerts.iter().take(MAX_ALERTS) {
+                lines.push(format!(
+                    "- {} {}",
+                    util::discord_timestamp(*at, TimestampStyle::ShortDateTime),
+                    alert
+                ));
+            }
+            if self.alerts.len() > MAX_ALERTS {
+                lines.push(format!("- and {} more", self.alerts.len() - MAX_ALERTS));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+async fn post(globals: &Globals, schedule: DigestSchedule, now: SystemTime) -> Result<()> {
+    // the period runs from the digest before this one, whether or not the bot was up for it
+    let due = schedule.last_due(now);
+    let since = schedule.last_due(due - Duration::from_secs(1));
+    let volumes = globals.archive.volumes(since, now);
+    let pending = match endpoint::get_outstanding_namereqs(&globals.ofapi).await {
+        Ok(reqs) => Some(reqs.len()),
+        Err(e) => {
+            println!(
+                "Couldn't count outstanding name requests for the digest: {}",
+                e
+            );
+            None
+        }
+    };
+    let stats = globals.state.lock().await.digest.take(now);
+    let report = stats.report(schedule.period, since, now, &volumes, pending);
+    globals
+        .sink
+        .send(globals.settings().mod_channel, &report)
+        .await?;
+    Ok(())
+}
+
+/// When the digest was last posted, kept in a file next to the archive so a
+/// digest that came due while the bot was down still goes out when it's back.
+struct LastPosted {
+    path: PathBuf,
+}
+impl LastPosted {
+    fn new(archive_path: &Path) -> Self {
+        let mut path = archive_path.as_os_str().to_owned();
+        path.push(".last-digest");
+        Self {
+            path: PathBuf::from(path),
+        }
+    }
+
+    fn load(&self) -> Option<SystemTime> {
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return None,
+            Err(e) => {
+                println!("Couldn't read {}: {}", self.path.display(), e);
+                return None;
+            }
+        };
+        match contents.trim().parse() {
+            Ok(secs) => Some(UNIX_EPOCH + Duration::from_secs(secs)),
+            Err(e) => {
+                println!("Bad time in {}: {}", self.path.display(), e);
+                None
+            }
+        }
+    }
+
+    fn save(&self, at: SystemTime) -> Result<()> {
+        let secs = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        // written to the side and swapped in, so a crash can't leave it empty
+        let mut temp_path = self.path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        std::fs::write(&temp_path, secs.to_string())?;
+        std::fs::rename(&temp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Whether a digest came due after the last one was posted.
+fn is_due(schedule: &DigestSchedule, last_posted: SystemTime, now: SystemTime) -> bool {
+    schedule.last_due(now) > last_posted
+}
+
+/// Posts the digest whenever it's due, going by the settings at the time.
+pub(crate) async fn run(globals: Arc<Globals>) {
+    let last_posted = LastPosted::new(globals.archive.path());
+    // without a digest on record, the first one is the next one due
+    let mut last = last_posted.load().unwrap_or_else(SystemTime::now);
+    loop {
+        tokio::time::sleep(CHECK_INTERVAL).await;
+        let now = SystemTime::now();
+        let Some(schedule) = globals.settings().digest else {
+            // a digest switched on by a reload starts from then
+            last = now;
+            continue;
+        };
+        if !is_due(&schedule, last, now) {
+            continue;
+        }
+        let Some(_task) = globals.shutdown.begin() else {
+            return;
+        };
+        println!("Posting the {:?} digest", schedule.period);
+        match post(&globals, schedule, now).await {
+            Ok(()) => {
+                last = now;
+                if let Err(e) = last_posted.save(now) {
+                    println!("Couldn't save when the digest was posted: {}", e);
+                }
+            }
+            // tried again at the next check
+            Err(e) => println!("Couldn't post the digest: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn update(player_count: usize) -> Notification {
+        Notification::Updated {
+            player_count,
+            events: vec![],
+        }
+    }
+
+    #[test]
+    fn schedule() {
+        let settings = testing::settings_with(serde_json::json!({
+            "digest": { "time": "09:30" }
+        }));
+        let daily = settings.digest.unwrap();
+        // 1970-01-12 was a Monday
+        let monday = 11 * 24 * 3600;
+        let nine_thirty = 9 * 3600 + 30 * 60;
+        assert_eq!(
+            daily.last_due(at(monday + nine_thirty)),
+            at(monday + nine_thirty)
+        );
+        assert_eq!(
+            daily.last_due(at(monday + nine_thirty - 1)),
+            at(monday - 24 * 3600 + nine_thirty)
+        );
+
+        let settings = testing::settings_with(serde_json::json!({
+            "digest": { "time": "09:30", "period": "weekly", "weekday": "wednesday" }
+        }));
+        let weekly = settings.digest.unwrap();
+        let wednesday = monday + 2 * 24 * 3600;
+        assert_eq!(
+            weekly.last_due(at(wednesday + 3600 * 20)),
+            at(wednesday + nine_thirty)
+        );
+        assert_eq!(
+            weekly.last_due(at(wednesday + 3600)),
+            at(wednesday - 7 * 24 * 3600 + nine_thirty)
+        );
+        assert_eq!(weekly.to_string(), "weekly on Wednesday at 09:30 UTC");
+    }
+
+    #[test]
+    fn missed_digest_is_posted() {
+        let settings = testing::settings_with(serde_json::json!({
+            "digest": { "time": "09:30" }
+        }));
+        let daily = settings.digest.unwrap();
+        let day = 24 * 3600;
+        let nine_thirty = 9 * 3600 + 30 * 60;
+        let posted = at(10 * day + nine_thirty);
+        assert!(!is_due(&daily, posted, at(11 * day)));
+        // down from before 09:30 the next day until after it
+        assert!(is_due(&daily, posted, at(11 * day + nine_thirty + 3600)));
+
+        let path = testing::temp_path("archive.jsonl");
+        let last_posted = LastPosted::new(&path);
+        assert_eq!(last_posted.load(), None);
+        last_posted.save(posted).unwrap();
+        assert_eq!(LastPosted::new(&path).load(), Some(posted));
+        let _ = std::fs::remove_file(&last_posted.path);
+    }
+
+    #[test]
+    fn report() {
+        let mut stats = Stats::new(at(0));
+        stats.record_notification(&Notification::Connected, at(0));
+        stats.record_notification(&update(2), at(60));
+        stats.record_alert("Config reload rejected", at(100));
+        stats.record_notification(&update(4), at(120));
+        stats.record_notification(&Notification::Disconnected, at(3600));
+        // only losing a live connection is an outage
+        stats.record_notification(&Notification::Disconnected, at(3660));
+        stats.record_notification(&Notification::Connected, at(5400));
+        stats.record_decision(UserId::new(10));
+        stats.record_decision(UserId::new(11));
+        stats.record_decision(UserId::new(11));
+
+        let volumes = Volumes {
+            chat_lines: 2,
+            emails: 1,
+            namereqs_received: 1,
+            namereqs_approved: 2,
+            namereqs_denied: 1,
+        };
+        let finished = stats.take(at(7200));
+        assert_eq!(
+            finished.report(DigestPeriod::Daily, at(0), at(7200), &volumes, Some(3)),
+            "**Daily digest** since <t:0:f>\n\
+             Population: peak **4**, average **3.0**\n\
+             Server uptime: **75.0%** (1h 30m), **1** outage(s)\n\
+             Chat: **2** lines, emails: **1**\n\
+             Name requests: **1** received, **2** approved, **1** denied, **3** pending\n\
+             Most active moderators: <@11> (2), <@10> (1)\n\
+             Alerts: **2**\n\
+             - <t:100:f> Config reload rejected\n\
+             - <t:3600:f> Lost connection to the monitor"
+        );
+
+        // the next period starts where that one ended, still online
+        let next = stats.take(at(7260));
+        assert_eq!(next.uptime, Duration::from_secs(60));
+        let report = next.report(
+            DigestPeriod::Weekly,
+            at(0),
+            at(7260),
+            &Volumes::default(),
+            None,
+        );
+        assert!(report.starts_with(
+            "**Weekly digest** since <t:0:f>\n\
+             Population, uptime and moderators since the bot started <t:7200:f>"
+        ));
+        assert!(report.contains("**unknown** pending"));
+        assert!(report.ends_with("Alerts: none"));
+    }
+}
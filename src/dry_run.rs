@@ -0,0 +1,243 @@
+//! Runs the monitor pipeline without Discord, writing out what the bot would
+//! have posted instead of posting it.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use async_trait::async_trait;
+use ffmonitor::{Monitor, MonitorNotification};
+use poise::serenity_prelude::{ChannelId, MessageId, RoleId};
+use tokio::sync::mpsc;
+
+use crate::{
+    config::Settings,
+    endpoint::{self, OfapiClient},
+    events::Notification,
+    monitor::{self, Output, Relay},
+    recording::{self, Recorder, Replay},
+    sink::{Button, Embed, MessageRef, Sink},
+    Result,
+};
+
+#[derive(Debug)]
+enum Target {
+    Stdout,
+    File(BufWriter<File>),
+}
+
+/// A sink that writes out what would have been posted.
+#[derive(Debug)]
+pub(crate) struct DryRun {
+    target: Mutex<Target>,
+    /// For naming channels in the output
+    settings: Settings,
+    next_id: Mutex<u64>,
+}
+impl DryRun {
+    pub(crate) fn new(path: Option<&str>, settings: Settings) -> Result<Self> {
+        let target = match path {
+            Some(path) => {
+                let file = File::create(path)
+                    .map_err(|e| format!("Couldn't create dry run output {}: {}", path, e))?;
+                Target::File(BufWriter::new(file))
+            }
+            None => Target::Stdout,
+        };
+        Ok(Self {
+            target: Mutex::new(target),
+            settings,
+            next_id: Mutex::new(0),
+        })
+    }
+
+    pub(crate) fn write(&self, line: &str) {
+        let mut target = self.target.lock().unwrap();
+        let res = match &mut *target {
+            Target::Stdout => {
+                println!("[dry run] {}", line);
+                Ok(())
+            }
+            Target::File(file) => writeln!(file, "{}", line).and_then(|_| file.flush()),
+        };
+        if let Err(e) = res {
+            println!("Couldn't write dry run output: {}", e);
+        }
+    }
+
+    fn post(&self, channel: ChannelId, content: &str) -> MessageRef {
+        let mut next_id = self.next_id.lock().unwrap();
+        *next_id += 1;
+        let message = MessageRef {
+            channel,
+            id: MessageId::new(*next_id),
+        };
+        // indent continuation lines so multi-line posts stay readable
+        let content = content.replace('\n', "\n    ");
+        self.write(&format!(
+            "{} ({}) #{}: {}",
+            channel_name(&self.settings, channel),
+            channel,
+            message.id,
+            content
+        ));
+        message
+    }
+}
+#[async_trait]
+impl Sink for DryRun {
+    async fn send(&self, channel: ChannelId, content: &str) -> Result<MessageRef> {
+        Ok(self.post(channel, content))
+    }
+
+    async fn send_embed(&self, channel: ChannelId, embed: Embed) -> Result<MessageRef> {
+        let mut lines = vec![format!(
+            "[embed #{:06x}] {}",
+            embed.colour,
+            embed.title.unwrap_or_default()
+        )];
+        for field in embed.fields {
+            lines.push(format!("{}: {}", field.name, field.value));
+        }
+        lines.extend(embed.description);
+        Ok(self.post(channel, &lines.join("\n")))
+    }
+
+    async fn send_pinging(
+        &self,
+        channel: ChannelId,
+        content: &str,
+        role: RoleId,
+    ) -> Result<MessageRef> {
+        Ok(self.post(channel, &format!("(pings role {}) {}", role, content)))
+    }
+
+    async fn send_with_buttons(
+        &self,
+        channel: ChannelId,
+        content: &str,
+        buttons: Vec<Button>,
+    ) -> Result<MessageRef> {
+        let buttons: Vec<String> = buttons.iter().map(|b| format!("[{}]", b.label)).collect();
+        Ok(self.post(channel, &format!("{} {}", content, buttons.join(" "))))
+    }
+
+    async fn set_presence(&self, text: &str) -> Result<()> {
+        self.write(&format!("Status: Listening to {}", text));
+        Ok(())
+    }
+
+    async fn delete(&self, message: MessageRef) -> Result<()> {
+        self.write(&format!("Delete #{}", message.id));
+        Ok(())
+    }
+}
+
+fn channel_name(settings: &Settings, channel: ChannelId) -> &'static str {
+    if Some(channel) == settings.log_channel {
+        "log channel"
+    } else if Some(channel) == settings.name_approvals_channel {
+        "name approvals channel"
+    } else if channel == settings.mod_channel {
+        "mod channel"
+    } else {
+        "channel"
+    }
+}
+
+/// Connects to the monitor (or plays back a recording) and writes out
+/// everything the bot would have done, until the monitor goes away.
+/// Name requests go through the impersonation checks, and any decision
+/// is written out along with the OFAPI request it would have made.
+pub(crate) async fn run(
+    dry_run: Arc<DryRun>,
+    monitor_address: &str,
+    ofapi: OfapiClient,
+    recorder: Option<Arc<Recorder>>,
+    replay: Option<Replay>,
+) -> Result<()> {
+    let settings = dry_run.settings.clone();
+    let ofapi = ofapi.with_dry_run(dry_run.clone());
+    dry_run.send(settings.mod_channel, "Bot started").await?;
+    match endpoint::get_outstanding_namereqs(&ofapi).await {
+        Ok(reqs) => println!(
+            "OFAPI credentials are valid ({} outstanding name requests)",
+            reqs.len()
+        ),
+        Err(e) => println!("OFAPI check failed: {}", e),
+    }
+
+    let (tx, mut notifications) = mpsc::unbounded_channel();
+    let _monitor = match replay {
+        Some(replay) => {
+            println!(
+                "Replaying {} instead of connecting to the monitor",
+                replay.path
+            );
+            tokio::spawn(async move {
+                let res = recording::replay(&replay, |notification, at| {
+                    let _ = tx.send((notification, at));
+                    async { Ok(()) }
+                })
+                .await;
+                match res {
+                    Ok(()) => println!("Replay finished"),
+                    Err(e) => println!("Error while replaying: {}", e),
+                }
+            });
+            None
+        }
+        None => {
+            let callback = move |notification: MonitorNotification| {
+                let notification = Notification::from(notification);
+                if let Some(recorder) = &recorder {
+                    if let Err(e) = recorder.record(&notification) {
+                        println!("Error while recording monitor event: {}", e);
+                    }
+                }
+                let _ = tx.send((notification, SystemTime::now()));
+            };
+            match Monitor::new_with_callback(monitor_address, Box::new(callback)) {
+                Ok(monitor) => Some(monitor),
+                Err(e) => return Err(format!("Error preparing ffmonitor: {:?}", e).into()),
+            }
+        }
+    };
+
+    let relay = Relay {
+        sink: dry_run.as_ref(),
+        ofapi: &ofapi,
+        archive: None,
+        settings: &settings,
+    };
+    // the status is set on every update, so only show it when it changes
+    let mut last_status = None;
+    while let Some((notification, at)) = notifications.recv().await {
+        match notification {
+            Notification::Connected => println!("Connected to monitor"),
+            Notification::Disconnected => println!("Disconnected from monitor"),
+            _ => {}
+        }
+        for output in monitor::process_notification(&settings, notification) {
+            match output {
+                Output::Status(num_players) if last_status == Some(num_players) => continue,
+                Output::Status(num_players) => last_status = Some(num_players),
+                Output::NameRequest {
+                    channel,
+                    name_request,
+                    ..
+                } => {
+                    // decisions only get written out, so replays can show them too
+                    monitor::relay_name_request(&relay, channel, name_request, false).await?;
+                    continue;
+                }
+                _ => {}
+            }
+            monitor::deliver(dry_run.as_ref(), output, at).await?;
+        }
+    }
+    Ok(())
+}
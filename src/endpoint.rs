@@ -1,9 +1,116 @@
-use std::{env, sync::OnceLock, time::Duration};
+use std::{fmt::Display, sync::Arc, time::Duration};
 
-use reqwest::{Client, StatusCode};
-use serde::Serialize;
+use reqwest::{Certificate, Client, Proxy, RequestBuilder, Response, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
 
-use crate::{Globals, NameRequest, Result};
+use crate::{config::Config, dry_run::DryRun, util, NameRequest, Result};
+
+const MAX_ATTEMPTS: u32 = 3;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_ERROR_BODY_LEN: usize = 200;
+
+#[derive(Debug)]
+pub(crate) enum OfapiError {
+    /// The token was missing or rejected (401/403)
+    Auth(Option<String>),
+    /// 404
+    NotFound(Option<String>),
+    /// 409
+    Conflict(Option<String>),
+    /// 5xx
+    Server(StatusCode, Option<String>),
+    /// Any other unsuccessful status
+    Rejected(StatusCode, Option<String>),
+    /// The request or response took too long
+    Timeout,
+    /// OFAPI couldn't be reached at all
+    Network(String),
+    /// OFAPI responded with something we couldn't make sense of
+    InvalidResponse(String),
+}
+impl OfapiError {
+    fn from_status(status: StatusCode, detail: Option<String>) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Self::Auth(detail),
+            StatusCode::NOT_FOUND => Self::NotFound(detail),
+            StatusCode::CONFLICT => Self::Conflict(detail),
+            s if s.is_server_error() => Self::Server(status, detail),
+            _ => Self::Rejected(status, detail),
+        }
+    }
+
+    fn is_retryable(&self) -> bool {
+        matches!(self, Self::Server(..) | Self::Timeout | Self::Network(_))
+    }
+
+    /// A message suitable for showing to the moderator who triggered the request.
+    pub(crate) fn user_message(&self) -> String {
+        let with_detail = |msg: &str, detail: &Option<String>| match detail {
+            Some(detail) => format!("{}: {}", msg, detail),
+            None => msg.to_string(),
+        };
+        match self {
+            Self::Auth(_) => "The bot isn't authorized to talk to OFAPI. Let an admin know.".into(),
+            Self::NotFound(detail) => with_detail("OFAPI couldn't find that", detail),
+            Self::Conflict(detail) => with_detail("OFAPI reported a conflict", detail),
+            Self::Server(..) => "OFAPI is having problems right now. Try again later.".into(),
+            Self::Rejected(_, detail) => with_detail("OFAPI rejected the request", detail),
+            Self::Timeout => "OFAPI took too long to respond. Try again later.".into(),
+            Self::Network(_) => "Couldn't reach OFAPI. Try again later.".into(),
+            Self::InvalidResponse(_) => "OFAPI sent a response the bot couldn't understand.".into(),
+        }
+    }
+}
+impl Display for OfapiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let detail = |detail: &Option<String>| match detail {
+            Some(detail) => format!(": {}", detail),
+            None => String::new(),
+        };
+        match self {
+            Self::Auth(d) => write!(f, "OFAPI auth error{}", detail(d)),
+            Self::NotFound(d) => write!(f, "OFAPI not found{}", detail(d)),
+            Self::Conflict(d) => write!(f, "OFAPI conflict{}", detail(d)),
+            Self::Server(status, d) => write!(f, "OFAPI server error {}{}", status, detail(d)),
+            Self::Rejected(status, d) => write!(f, "OFAPI error {}{}", status, detail(d)),
+            Self::Timeout => write!(f, "OFAPI timed out"),
+            Self::Network(e) => write!(f, "OFAPI network error: {}", e),
+            Self::InvalidResponse(e) => write!(f, "OFAPI invalid response: {}", e),
+        }
+    }
+}
+impl std::error::Error for OfapiError {}
+impl From<reqwest::Error> for OfapiError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            Self::Timeout
+        } else if e.is_decode() {
+            Self::InvalidResponse(e.to_string())
+        } else {
+            Self::Network(e.to_string())
+        }
+    }
+}
+
+type OfapiResult<T> = std::result::Result<T, OfapiError>;
+
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    #[serde(alias = "message")]
+    error: String,
+}
+
+#[derive(Debug, Serialize)]
+struct LoginRequest<'a> {
+    username: &'a str,
+    password: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginResponse {
+    token: String,
+}
 
 #[derive(Debug, Serialize)]
 struct NameRequestDecision {
@@ -13,46 +120,285 @@ struct NameRequestDecision {
     by: String,
 }
 
-fn get_http_client() -> &'static Client {
-    static CLIENT: OnceLock<Client> = OnceLock::new();
-    CLIENT.get_or_init(|| {
-        Client::builder()
+const DEFAULT_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_LOGIN_PATH: &str = "/auth";
+
+/// How to reach OFAPI, as set in the config.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct OfapiOptions {
+    base_url: String,
+    timeout: Duration,
+    connect_timeout: Option<Duration>,
+    ca_certs: Vec<String>,
+    proxy: Option<String>,
+    login_path: String,
+}
+impl OfapiOptions {
+    pub(crate) fn from_config(config: &Config) -> Result<Self> {
+        // a bare host (the old format) means https
+        let endpoint = config.ofapi_endpoint.trim_end_matches('/');
+        let base_url = if endpoint.contains("://") {
+            endpoint.to_string()
+        } else {
+            format!("https://{}", endpoint)
+        };
+        let url = Url::parse(&base_url).map_err(|e| format!("Bad ofapi_endpoint: {}", e))?;
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Err(format!("Bad ofapi_endpoint: unsupported scheme {}", url.scheme()).into());
+        }
+
+        Ok(Self {
+            base_url,
+            timeout: Duration::from_secs(config.ofapi_timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS)),
+            connect_timeout: config.ofapi_connect_timeout_secs.map(Duration::from_secs),
+            ca_certs: config.ofapi_ca_certs.clone(),
+            proxy: config.ofapi_proxy.clone(),
+            login_path: config
+                .ofapi_login_path
+                .clone()
+                .unwrap_or(DEFAULT_LOGIN_PATH.to_string()),
+        })
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_base_url(base_url: String) -> Self {
+        Self {
+            base_url,
+            timeout: Duration::from_secs(5),
+            connect_timeout: None,
+            ca_certs: Vec::new(),
+            proxy: None,
+            login_path: DEFAULT_LOGIN_PATH.to_string(),
+        }
+    }
+}
+
+/// Username and password for OFAPI's login endpoint
+pub(crate) struct Credentials {
+    username: String,
+    password: String,
+}
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Where the bot gets its OFAPI access from: a fixed token, a login, or both
+/// (in which case the login is used to get a new token once the first one expires).
+#[derive(Debug)]
+pub(crate) struct OfapiAuth {
+    token: Option<String>,
+    credentials: Option<Credentials>,
+}
+impl OfapiAuth {
+    /// Environment variables (or their `_FILE` variants) take precedence over the config.
+    pub(crate) fn load(config: &Config) -> Result<Self> {
+        let token = util::get_secret("OFAPI_TOKEN")?.or(config.ofapi_token.clone());
+        let username = util::get_secret("OFAPI_USERNAME")?.or(config.ofapi_username.clone());
+        let password = util::get_secret("OFAPI_PASSWORD")?.or(config.ofapi_password.clone());
+        let credentials = match (username, password) {
+            (Some(username), Some(password)) => Some(Credentials { username, password }),
+            (None, None) => None,
+            _ => return Err("OFAPI username and password must be set together".into()),
+        };
+        Ok(Self { token, credentials })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.token.is_none() && self.credentials.is_none()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_token(token: &str) -> Self {
+        Self {
+            token: Some(token.to_string()),
+            credentials: None,
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn with_login(token: Option<&str>, username: &str, password: &str) -> Self {
+        Self {
+            token: token.map(|t| t.to_string()),
+            credentials: Some(Credentials {
+                username: username.to_string(),
+                password: password.to_string(),
+            }),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct OfapiClient {
+    pub(crate) options: OfapiOptions,
+    client: Client,
+    credentials: Option<Credentials>,
+    token: RwLock<Option<String>>,
+    /// If set, requests that change anything are written here instead of sent
+    dry_run: Option<Arc<DryRun>>,
+}
+impl OfapiClient {
+    pub(crate) fn new(options: OfapiOptions, auth: OfapiAuth) -> Result<Self> {
+        let mut builder = Client::builder()
             .user_agent(format!("computress-rs/{}", env!("CARGO_PKG_VERSION")))
-            .timeout(Duration::from_secs(10))
-            .build()
-            .unwrap()
-    })
+            .timeout(options.timeout);
+        if let Some(connect_timeout) = options.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        for path in &options.ca_certs {
+            let pem = std::fs::read(path)
+                .map_err(|e| format!("Couldn't read CA certificate {}: {}", path, e))?;
+            let certs = Certificate::from_pem_bundle(&pem)
+                .map_err(|e| format!("Bad CA certificate {}: {}", path, e))?;
+            for cert in certs {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        if let Some(proxy) = &options.proxy {
+            let proxy = Proxy::all(proxy).map_err(|e| format!("Bad ofapi_proxy: {}", e))?;
+            builder = builder.proxy(proxy);
+        }
+        let client = builder.build()?;
+        Ok(Self {
+            options,
+            client,
+            credentials: auth.credentials,
+            token: RwLock::new(auth.token),
+            dry_run: None,
+        })
+    }
+
+    pub(crate) fn with_dry_run(mut self, dry_run: Arc<DryRun>) -> Self {
+        self.dry_run = Some(dry_run);
+        self
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.options.base_url, path)
+    }
+
+    /// Gets a fresh token from OFAPI's login endpoint to replace `failed`,
+    /// unless another request already replaced it while we waited for the lock.
+    async fn login(&self, failed: Option<&str>) -> OfapiResult<String> {
+        let Some(credentials) = &self.credentials else {
+            return Err(OfapiError::Auth(Some("no OFAPI login configured".into())));
+        };
+        let mut token = self.token.write().await;
+        if let Some(current) = token.as_deref().filter(|&current| Some(current) != failed) {
+            return Ok(current.to_string());
+        }
+
+        let endpoint = self.url(&self.options.login_path);
+        let req = LoginRequest {
+            username: &credentials.username,
+            password: &credentials.password,
+        };
+        let resp = send(|| self.client.post(&endpoint).json(&req), true).await?;
+
+        // the token may come back wrapped in JSON or on its own
+        let body = resp.text().await?;
+        let new_token = match serde_json::from_str::<LoginResponse>(&body) {
+            Ok(login) => login.token,
+            Err(_) => body.trim().to_string(),
+        };
+        if new_token.is_empty() {
+            return Err(OfapiError::InvalidResponse("empty login token".into()));
+        }
+        println!("Logged in to OFAPI as {}", credentials.username);
+        *token = Some(new_token.clone());
+        Ok(new_token)
+    }
+
+    async fn get_token(&self) -> OfapiResult<String> {
+        if let Some(token) = self.token.read().await.clone() {
+            return Ok(token);
+        }
+        self.login(None).await
+    }
+
+    /// Sends a request with the current token, logging in again and retrying
+    /// once if OFAPI says the token is no good.
+    async fn send_authed(
+        &self,
+        build: impl Fn(&str) -> RequestBuilder,
+        idempotent: bool,
+    ) -> OfapiResult<Response> {
+        let token = self.get_token().await?;
+        match send(|| build(&token), idempotent).await {
+            Err(OfapiError::Auth(_)) if self.credentials.is_some() => {
+                let token = self.login(Some(&token)).await?;
+                send(|| build(&token), idempotent).await
+            }
+            res => res,
+        }
+    }
 }
 
-fn get_token() -> Result<String> {
-    env::var("OFAPI_TOKEN").map_err(|_| "OFAPI_TOKEN environment variable missing".into())
+/// Pulls the error message out of an OFAPI error response, if there is one.
+async fn parse_error_body(resp: Response) -> Option<String> {
+    let text = resp.text().await.ok()?;
+    let text = text.trim();
+    if let Ok(body) = serde_json::from_str::<ErrorBody>(text) {
+        return Some(body.error);
+    }
+    if text.is_empty() || text.starts_with('<') {
+        // empty or an HTML error page
+        return None;
+    }
+    Some(text.chars().take(MAX_ERROR_BODY_LEN).collect())
 }
 
-pub(crate) async fn get_outstanding_namereqs(globals: &Globals) -> Result<Vec<NameRequest>> {
-    let endpoint = format!("https://{}/namereq", globals.ofapi_endpoint);
-    let token = get_token()?;
-    let resp = get_http_client()
-        .get(&endpoint)
-        .bearer_auth(token)
-        .send()
-        .await?;
+async fn send_once(req: RequestBuilder) -> OfapiResult<Response> {
+    let resp = req.send().await?;
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+    let detail = parse_error_body(resp).await;
+    Err(OfapiError::from_status(status, detail))
+}
 
-    let status_code = resp.status();
-    if !status_code.is_success() {
-        return Err(format!("OFAPI error: {} {}", endpoint, status_code).into());
+/// Sends a request, retrying with exponential backoff on transient failures
+/// if the request is safe to repeat.
+async fn send(build: impl Fn() -> RequestBuilder, idempotent: bool) -> OfapiResult<Response> {
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        match send_once(build()).await {
+            Err(e) if idempotent && e.is_retryable() && attempt < MAX_ATTEMPTS => {
+                println!(
+                    "OFAPI request failed (attempt {}/{}), retrying in {:?}: {}",
+                    attempt, MAX_ATTEMPTS, backoff, e
+                );
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+            res => return res,
+        }
     }
+}
+
+pub(crate) async fn get_outstanding_namereqs(ofapi: &OfapiClient) -> OfapiResult<Vec<NameRequest>> {
+    let endpoint = ofapi.url("/namereq");
+    let resp = ofapi
+        .send_authed(|token| ofapi.client.get(&endpoint).bearer_auth(token), true)
+        .await?;
 
     let body = resp.json().await?;
     Ok(body)
 }
 
 pub(crate) async fn send_name_request_decision(
-    globals: &Globals,
+    ofapi: &OfapiClient,
     namereq: &NameRequest,
     decision: &str,
     by: &str,
-) -> Result<bool> {
-    let endpoint = format!("https://{}/namereq", globals.ofapi_endpoint);
+) -> OfapiResult<bool> {
+    let endpoint = ofapi.url("/namereq");
     let req = NameRequestDecision {
         player_uid: namereq.player_uid,
         requested_name: namereq.requested_name.clone(),
@@ -60,19 +406,117 @@ pub(crate) async fn send_name_request_decision(
         by: by.to_string(),
     };
 
-    let token = get_token()?;
-    let resp = get_http_client()
-        .post(&endpoint)
-        .bearer_auth(token)
-        .json(&req)
-        .send()
+    if let Some(dry_run) = &ofapi.dry_run {
+        let body =
+            serde_json::to_string(&req).map_err(|e| OfapiError::InvalidResponse(e.to_string()))?;
+        dry_run.write(&format!("OFAPI: POST {} {}", endpoint, body));
+        return Ok(true);
+    }
+
+    // not retried, since we can't tell a repeated decision apart from one made by someone else
+    let resp = ofapi
+        .send_authed(
+            |token| ofapi.client.post(&endpoint).bearer_auth(token).json(&req),
+            false,
+        )
         .await?;
 
-    let status_code = resp.status();
-    if !status_code.is_success() {
-        return Err(format!("OFAPI error: {} {}", endpoint, status_code).into());
+    let updated = resp.status() != StatusCode::ALREADY_REPORTED;
+    Ok(updated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, MockOfapi};
+
+    #[tokio::test]
+    async fn outstanding_namereqs() {
+        let mock = MockOfapi::start().await;
+        mock.respond(
+            "GET",
+            "/namereq",
+            200,
+            r#"[{"player_uid": 1, "requested_name": "Alice"}, {"player_uid": 2, "requested_name": "Bob"}]"#,
+        );
+
+        let reqs = get_outstanding_namereqs(&mock.client()).await.unwrap();
+        assert_eq!(reqs.len(), 2);
+        assert_eq!(reqs[1].player_uid, 2);
+        assert_eq!(reqs[1].requested_name, "Bob");
+        assert_eq!(mock.requests()[0].method, "GET");
     }
 
-    let updated = status_code != StatusCode::ALREADY_REPORTED;
-    Ok(updated)
+    #[tokio::test]
+    async fn idempotent_requests_are_retried() {
+        let mock = MockOfapi::start().await;
+        mock.respond("GET", "/namereq", 503, "");
+        mock.respond("GET", "/namereq", 200, "[]");
+
+        let reqs = get_outstanding_namereqs(&mock.client()).await.unwrap();
+        assert!(reqs.is_empty());
+        assert_eq!(mock.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn error_bodies_are_parsed() {
+        let mock = MockOfapi::start().await;
+        mock.respond("GET", "/namereq", 404, r#"{"message": "no such thing"}"#);
+
+        let err = get_outstanding_namereqs(&mock.client()).await.unwrap_err();
+        assert!(matches!(err, OfapiError::NotFound(Some(ref d)) if d == "no such thing"));
+    }
+
+    #[tokio::test]
+    async fn expired_token_is_refreshed() {
+        let mock = MockOfapi::start().await;
+        mock.respond("POST", "/auth", 200, r#"{"token": "fresh"}"#);
+        mock.respond("GET", "/namereq", 401, "");
+        mock.respond("GET", "/namereq", 200, "[]");
+
+        let client = mock.client_with_auth(OfapiAuth::with_login(Some("stale"), "bot", "hunter2"));
+        get_outstanding_namereqs(&client).await.unwrap();
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].header("authorization"), Some("Bearer stale"));
+        assert_eq!(requests[1].path, "/auth");
+        assert_eq!(requests[1].json()["username"], "bot");
+        assert_eq!(requests[2].header("authorization"), Some("Bearer fresh"));
+    }
+
+    #[tokio::test]
+    async fn token_is_only_refreshed_once() {
+        let mock = MockOfapi::start().await;
+        mock.respond("POST", "/auth", 200, r#"{"token": "fresh"}"#);
+
+        let client = mock.client_with_auth(OfapiAuth::with_login(Some("stale"), "bot", "hunter2"));
+        // as if two requests were turned away with the stale token at once
+        assert_eq!(client.login(Some("stale")).await.unwrap(), "fresh");
+        assert_eq!(client.login(Some("stale")).await.unwrap(), "fresh");
+        assert_eq!(mock.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn decisions_are_not_sent_in_dry_run() {
+        let mock = MockOfapi::start().await;
+        let path = testing::temp_path("dry-run");
+        let dry_run = Arc::new(DryRun::new(path.to_str(), testing::settings()).unwrap());
+        let client = mock.client().with_dry_run(dry_run);
+
+        let namereq = NameRequest {
+            player_uid: 42,
+            requested_name: "Bob".to_string(),
+        };
+        let updated = send_name_request_decision(&client, &namereq, "approved", "mod#0001")
+            .await
+            .unwrap();
+        assert!(updated);
+        assert!(mock.requests().is_empty());
+
+        let output = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert!(output.starts_with(&format!("OFAPI: POST {}/namereq ", mock.base_url())));
+        assert!(output.contains(r#""decision":"approved""#));
+    }
 }
@@ -0,0 +1,106 @@
+//! Our own copies of the monitor's notifications, which (unlike ffmonitor's)
+//! can be recorded, replayed and constructed in tests.
+
+use ffmonitor::MonitorNotification;
+use serde::{Deserialize, Serialize};
+
+use crate::NameRequest;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ChatEvent {
+    pub(crate) kind: String,
+    pub(crate) from: String,
+    pub(crate) to: Option<String>,
+    pub(crate) message: String,
+}
+impl From<ffmonitor::ChatEvent> for ChatEvent {
+    fn from(chat: ffmonitor::ChatEvent) -> Self {
+        Self {
+            kind: chat.kind.to_string(),
+            from: chat.from,
+            to: chat.to,
+            message: chat.message,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct EmailEvent {
+    pub(crate) from: String,
+    pub(crate) to: String,
+    pub(crate) subject: Option<String>,
+    pub(crate) body: Vec<String>,
+}
+impl From<ffmonitor::EmailEvent> for EmailEvent {
+    fn from(email: ffmonitor::EmailEvent) -> Self {
+        Self {
+            from: email.from,
+            to: email.to,
+            subject: email.subject,
+            body: email.body,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct BroadcastEvent {
+    pub(crate) scope: String,
+    pub(crate) from: String,
+    pub(crate) message: String,
+}
+impl From<ffmonitor::BroadcastEvent> for BroadcastEvent {
+    fn from(bcast: ffmonitor::BroadcastEvent) -> Self {
+        Self {
+            scope: format!("{:?}", bcast.scope),
+            from: bcast.from,
+            message: bcast.message,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum Event {
+    Chat(ChatEvent),
+    Email(EmailEvent),
+    Broadcast(BroadcastEvent),
+    NameRequest(NameRequest),
+}
+impl Event {
+    fn from_monitor(event: ffmonitor::Event) -> Option<Self> {
+        match event {
+            ffmonitor::Event::Chat(chat) => Some(Self::Chat(chat.into())),
+            ffmonitor::Event::Email(email) => Some(Self::Email(email.into())),
+            ffmonitor::Event::Broadcast(bcast) => Some(Self::Broadcast(bcast.into())),
+            ffmonitor::Event::NameRequest(namereq) => Some(Self::NameRequest(namereq.into())),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum Notification {
+    Connected,
+    Disconnected,
+    Updated {
+        player_count: usize,
+        events: Vec<Event>,
+    },
+}
+impl From<MonitorNotification> for Notification {
+    fn from(notification: MonitorNotification) -> Self {
+        match notification {
+            MonitorNotification::Connected => Self::Connected,
+            MonitorNotification::Disconnected => Self::Disconnected,
+            MonitorNotification::Updated(update) => Self::Updated {
+                player_count: update.get_player_count(),
+                events: update
+                    .get_events()
+                    .into_iter()
+                    .filter_map(Event::from_monitor)
+                    .collect(),
+            },
+        }
+    }
+}
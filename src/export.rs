@@ -0,0 +1,355 @@
+//! Archived events as files for moderators to download.
+
+use std::io::Write;
+
+use flate2::{write::GzEncoder, Compression};
+
+use crate::{archive::Record, events::Event, util, Result};
+
+/// The most Discord accepts uploading with one message in a server without boosts,
+/// across all of its attachments
+pub(crate) const MAX_UPLOAD_SIZE: usize = 8 * 1024 * 1024;
+/// How many attachments Discord allows on one message
+const MAX_FILES_PER_MESSAGE: usize = 10;
+
+/// Parts aim for this much of the limit, since some compress worse than the whole export
+const PART_MARGIN: f64 = 0.9;
+
+const CSV_HEADER: &str = "time,type,kind,from,to,subject,message";
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, poise::ChoiceParameter)]
+pub(crate) enum Format {
+    #[default]
+    #[name = "JSON Lines"]
+    Jsonl,
+    #[name = "CSV"]
+    Csv,
+}
+impl Format {
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Jsonl => "jsonl",
+            Format::Csv => "csv",
+        }
+    }
+
+    fn header(&self) -> Option<&'static str> {
+        match self {
+            Format::Jsonl => None,
+            Format::Csv => Some(CSV_HEADER),
+        }
+    }
+
+    fn row(&self, record: &Record) -> Result<String> {
+        match self {
+            Format::Jsonl => Ok(serde_json::to_string(record)?),
+            Format::Csv => Ok(csv_row(record)),
+        }
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn csv_row(record: &Record) -> String {
+    let (event_type, kind, subject, message) = match &record.event {
+        Event::Chat(chat) => ("chat", chat.kind.as_str(), "", chat.message.clone()),
+        Event::Email(email) => (
+            "email",
+            "",
+            email.subject.as_deref().unwrap_or_default(),
+            email.body.join("\n"),
+        ),
+        Event::Broadcast(bcast) => ("broadcast", bcast.scope.as_str(), "", bcast.message.clone()),
+        Event::NameRequest(namereq) => ("name_request", "", "", namereq.requested_name.clone()),
+    };
+    let time = util::format_utc(record.time());
+    let fields = [
+        time.as_str(),
+        event_type,
+        kind,
+        record.sender().unwrap_or_default(),
+        record.recipient().unwrap_or_default(),
+        subject,
+        &message,
+    ];
+    let fields: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+    fields.join(",")
+}
+
+/// A file to attach to a reply.
+#[derive(Debug, PartialEq)]
+pub(crate) struct File {
+    pub(crate) name: String,
+    pub(crate) data: Vec<u8>,
+}
+
+fn gzip(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Joins `rows` into one file's contents, starting with the header if the format has one.
+fn join(format: Format, rows: &[String]) -> Vec<u8> {
+    let mut data = String::new();
+    for line in format
+        .header()
+        .into_iter()
+        .chain(rows.iter().map(|r| r.as_str()))
+    {
+        data.push_str(line);
+        data.push('\n');
+    }
+    data.into_bytes()
+}
+
+/// Splits `rows` into runs of at most `target` bytes, except for rows too big to share.
+fn split(rows: &[String], target: usize) -> Vec<&[String]> {
+    let mut chunks = Vec::new();
+    let (mut start, mut size) = (0, 0);
+    for (i, row) in rows.iter().enumerate() {
+        if size > 0 && size + row.len() + 1 > target {
+            chunks.push(&rows[start..i]);
+            (start, size) = (i, 0);
+        }
+        size += row.len() + 1;
+    }
+    chunks.push(&rows[start..]);
+    chunks
+}
+
+/// Gzips each run of rows, halving any that still come out bigger than `limit`.
+fn gzip_parts(format: Format, chunks: Vec<&[String]>, limit: usize) -> Result<Vec<Vec<u8>>> {
+    let mut parts = Vec::new();
+    // a stack, so halves are done in order
+    let mut pending = chunks;
+    pending.reverse();
+    while let Some(chunk) = pending.pop() {
+        let part = gzip(&join(format, chunk))?;
+        if part.len() <= limit {
+            parts.push(part);
+            continue;
+        }
+        if chunk.len() == 1 {
+            return Err("A single event is too big to upload".into());
+        }
+        let (first, second) = chunk.split_at(chunk.len() / 2);
+        pending.push(second);
+        pending.push(first);
+    }
+    Ok(parts)
+}
+
+/// Writes `records` out as files of at most `limit` bytes each: one plain file if
+/// it fits, otherwise gzipped, and split into parts if that's still too big.
+pub(crate) fn export(
+    records: &[Record],
+    format: Format,
+    name: &str,
+    limit: usize,
+) -> Result<Vec<File>> {
+    let rows = records
+        .iter()
+        .map(|record| format.row(record))
+        .collect::<Result<Vec<String>>>()?;
+    let file_name = format!("{}.{}", name, format.extension());
+
+    let data = join(format, &rows);
+    if data.len() <= limit {
+        return Ok(vec![File {
+            name: file_name,
+            data,
+        }]);
+    }
+    let compressed = gzip(&data)?;
+    if compressed.len() <= limit {
+        return Ok(vec![File {
+            name: format!("{}.gz", file_name),
+            data: compressed,
+        }]);
+    }
+
+    // size the parts by how well the whole export compressed
+    let ratio = compressed.len() as f64 / data.len() as f64;
+    let target = ((limit as f64 / ratio * PART_MARGIN) as usize).max(1);
+    let parts = gzip_parts(format, split(&rows, target), limit)?;
+    let count = parts.len();
+    let files = parts
+        .into_iter()
+        .enumerate()
+        .map(|(i, data)| File {
+            name: format!(
+                "{}-part{}of{}.{}.gz",
+                name,
+                i + 1,
+                count,
+                format.extension()
+            ),
+            data,
+        })
+        .collect();
+    Ok(files)
+}
+
+/// Groups `files` into messages that each stay within Discord's attachment count
+/// and `limit` bytes in total, keeping them in order.
+pub(crate) fn batch(files: Vec<File>, limit: usize) -> Vec<Vec<File>> {
+    let mut messages: Vec<Vec<File>> = Vec::new();
+    let mut size = 0;
+    for file in files {
+        match messages.last_mut() {
+            Some(message)
+                if message.len() < MAX_FILES_PER_MESSAGE && size + file.data.len() <= limit =>
+            {
+                size += file.data.len();
+                message.push(file);
+            }
+            _ => {
+                size = file.data.len();
+                messages.push(vec![file]);
+            }
+        }
+    }
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+    use crate::events::{ChatEvent, EmailEvent};
+
+    fn chat(at_secs: u64, from: &str, message: &str) -> Record {
+        Record {
+            at_ms: at_secs * 1000,
+            event: Event::Chat(ChatEvent {
+                kind: "FreeChat".to_string(),
+                from: from.to_string(),
+                to: None,
+                message: message.to_string(),
+            }),
+            message: None,
+            decision: None,
+        }
+    }
+
+    fn gunzip(data: &[u8]) -> String {
+        let mut text = String::new();
+        GzDecoder::new(data).read_to_string(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn csv() {
+        let records = vec![
+            chat(60, "Alice", "hi, \"Bob\""),
+            Record {
+                at_ms: 120_000,
+                event: Event::Email(EmailEvent {
+                    from: "Bob".to_string(),
+                    to: "Alice".to_string(),
+                    subject: Some("Re: hi".to_string()),
+                    body: vec!["line one".to_string(), "line two".to_string()],
+                }),
+                message: None,
+                decision: None,
+            },
+        ];
+        let files = export(&records, Format::Csv, "export", MAX_UPLOAD_SIZE).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].name, "export.csv");
+        assert_eq!(
+            String::from_utf8(files[0].data.clone()).unwrap(),
+            "time,type,kind,from,to,subject,message\n\
+             1970-01-01T00:01:00Z,chat,FreeChat,Alice,,,\"hi, \"\"Bob\"\"\"\n\
+             1970-01-01T00:02:00Z,email,,Bob,Alice,Re: hi,\"line one\nline two\"\n"
+        );
+    }
+
+    #[test]
+    fn jsonl() {
+        let records = vec![chat(60, "Alice", "hello"), chat(61, "Bob", "hey")];
+        let files = export(&records, Format::Jsonl, "export", MAX_UPLOAD_SIZE).unwrap();
+        assert_eq!(files[0].name, "export.jsonl");
+        let text = String::from_utf8(files[0].data.clone()).unwrap();
+        let parsed: Vec<Record> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(parsed, records);
+    }
+
+    #[test]
+    fn big_exports_are_compressed_then_split() {
+        let records: Vec<Record> = (0..200).map(|i| chat(i, "Alice", "hello")).collect();
+        let plain = export(&records, Format::Csv, "export", MAX_UPLOAD_SIZE).unwrap();
+        let plain = String::from_utf8(plain[0].data.clone()).unwrap();
+
+        let limit = plain.len() - 1;
+        let files = export(&records, Format::Csv, "export", limit).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].name, "export.csv.gz");
+        assert_eq!(gunzip(&files[0].data), plain);
+
+        // made-up text that doesn't compress well
+        let records: Vec<Record> = (0..200)
+            .map(|i| {
+                chat(
+                    i,
+                    "Alice",
+                    &format!("{:x}", (i + 1).wrapping_mul(0x9e3779b97f4a7c15)),
+                )
+            })
+            .collect();
+        let files = export(&records, Format::Jsonl, "export", 2048).unwrap();
+        assert!(files.len() > 1);
+        assert_eq!(
+            files[0].name,
+            format!("export-part1of{}.jsonl.gz", files.len())
+        );
+        let mut lines = Vec::new();
+        for file in &files {
+            assert!(file.data.len() <= 2048);
+            lines.extend(gunzip(&file.data).lines().map(|l| l.to_string()));
+        }
+        assert_eq!(lines.len(), records.len());
+    }
+
+    #[test]
+    fn files_are_batched_by_size_and_count() {
+        let file = |n: usize, size: usize| File {
+            name: format!("part{}", n),
+            data: vec![0; size],
+        };
+        let names = |messages: &[Vec<File>]| -> Vec<Vec<String>> {
+            messages
+                .iter()
+                .map(|m| m.iter().map(|f| f.name.clone()).collect())
+                .collect()
+        };
+
+        // three big parts can't share a message
+        let files = vec![file(1, 60), file(2, 60), file(3, 30), file(4, 10)];
+        assert_eq!(
+            names(&batch(files, 100)),
+            [vec!["part1"], vec!["part2", "part3", "part4"]]
+        );
+
+        // nor can more than ten small ones
+        let files = (1..=12).map(|n| file(n, 1)).collect();
+        let messages = batch(files, 100);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].len(), 10);
+        assert_eq!(messages[1].len(), 2);
+        assert!(batch(vec![], 100).is_empty());
+    }
+}
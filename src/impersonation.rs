@@ -0,0 +1,298 @@
+//! Spotting requested names that imitate someone else's.
+
+use std::fmt::Display;
+
+use crate::{config::Impersonation, util, NameRequest};
+
+/// Most matches listed in a warning
+const MAX_LISTED: usize = 5;
+
+/// Characters that look like (or get typed instead of) a plain lowercase letter.
+/// Looked up both before and after lowercasing, so capitals only need listing
+/// when they lowercase to something that looks different.
+const CONFUSABLES: &[(char, char)] = &[
+    // leetspeak
+    ('0', 'o'),
+    ('1', 'l'),
+    ('|', 'l'),
+    ('i', 'l'),
+    ('2', 'z'),
+    ('3', 'e'),
+    ('4', 'a'),
+    ('@', 'a'),
+    ('5', 's'),
+    ('$', 's'),
+    ('6', 'b'),
+    ('7', 't'),
+    ('8', 'b'),
+    ('9', 'g'),
+    // accents
+    ('à', 'a'),
+    ('á', 'a'),
+    ('â', 'a'),
+    ('ã', 'a'),
+    ('ä', 'a'),
+    ('å', 'a'),
+    ('ç', 'c'),
+    ('è', 'e'),
+    ('é', 'e'),
+    ('ê', 'e'),
+    ('ë', 'e'),
+    ('ì', 'l'),
+    ('í', 'l'),
+    ('î', 'l'),
+    ('ï', 'l'),
+    ('ñ', 'n'),
+    ('ò', 'o'),
+    ('ó', 'o'),
+    ('ô', 'o'),
+    ('õ', 'o'),
+    ('ö', 'o'),
+    ('ø', 'o'),
+    ('ù', 'u'),
+    ('ú', 'u'),
+    ('û', 'u'),
+    ('ü', 'u'),
+    ('ý', 'y'),
+    ('ÿ', 'y'),
+    // Cyrillic
+    ('а', 'a'),
+    ('в', 'b'),
+    ('В', 'b'),
+    ('е', 'e'),
+    ('н', 'h'),
+    ('Н', 'h'),
+    ('і', 'l'),
+    ('ј', 'j'),
+    ('к', 'k'),
+    ('м', 'm'),
+    ('о', 'o'),
+    ('р', 'p'),
+    ('с', 'c'),
+    ('ѕ', 's'),
+    ('т', 't'),
+    ('Т', 't'),
+    ('у', 'y'),
+    ('х', 'x'),
+    // Greek
+    ('α', 'a'),
+    ('β', 'b'),
+    ('ε', 'e'),
+    ('Η', 'h'),
+    ('ι', 'l'),
+    ('κ', 'k'),
+    ('Μ', 'm'),
+    ('Ν', 'n'),
+    ('ν', 'v'),
+    ('ο', 'o'),
+    ('ρ', 'p'),
+    ('Τ', 't'),
+    ('τ', 't'),
+    ('υ', 'u'),
+    ('Υ', 'y'),
+    ('χ', 'x'),
+    ('Ζ', 'z'),
+];
+
+/// Letter pairs that read as one letter at a glance
+const LOOKALIKE_PAIRS: &[(&str, &str)] = &[("rn", "m"), ("vv", "w")];
+
+fn unconfuse(c: char) -> char {
+    CONFUSABLES
+        .iter()
+        .find(|(from, _)| *from == c)
+        .map_or(c, |(_, to)| *to)
+}
+
+/// A name boiled down to what it looks like, so that "Bob", "b o b", "B0B" and "Вob" all match.
+pub(crate) fn skeleton(name: &str) -> String {
+    let mut skeleton: String = name
+        .chars()
+        // fullwidth forms of ASCII
+        .map(|c| match c as u32 {
+            0xff01..=0xff5e => char::from_u32(c as u32 - 0xfee0).unwrap_or(c),
+            _ => c,
+        })
+        .map(unconfuse)
+        .flat_map(char::to_lowercase)
+        .map(unconfuse)
+        .filter(|c| c.is_alphanumeric())
+        .collect();
+    for (pair, letter) in LOOKALIKE_PAIRS {
+        skeleton = skeleton.replace(pair, letter);
+    }
+    skeleton
+}
+
+/// Whose name a requested name looks like.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Source {
+    Player,
+    Staff,
+    Protected,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Match {
+    pub(crate) name: String,
+    pub(crate) source: Source,
+    /// Set when only part of the requested name looks like `name`
+    pub(crate) partial: bool,
+}
+impl Display for Match {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let source = match self.source {
+            Source::Player => "player",
+            Source::Staff => "staff name",
+            Source::Protected => "protected name",
+        };
+        write!(f, "{} **{}**", source, self.name)?;
+        if self.partial {
+            write!(f, " (in part)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Finds the names `requested` could be passed off as. `players` and staff names count
+/// when they look the same; protected names count anywhere in the requested name.
+pub(crate) fn check(requested: &str, players: &[String], rules: &Impersonation) -> Vec<Match> {
+    let requested = skeleton(requested);
+    if requested.is_empty() {
+        return vec![];
+    }
+    let found = |names: &[String], source, in_part: bool| {
+        names
+            .iter()
+            .filter_map(|name| {
+                let looks = skeleton(name);
+                let partial = looks != requested;
+                let found =
+                    !partial || (in_part && !looks.is_empty() && requested.contains(&looks));
+                found.then(|| Match {
+                    name: name.clone(),
+                    source,
+                    partial,
+                })
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut matches = found(&rules.staff_names, Source::Staff, false);
+    matches.extend(found(&rules.protected_names, Source::Protected, true));
+    matches.extend(found(players, Source::Player, false));
+    matches
+}
+
+/// Whether a request with these matches should be denied without asking anyone.
+/// Lookalikes of other players' names could be coincidence, and so could a protected
+/// name inside an ordinary word ("Admin" in "Badminton"), so those never are.
+pub(crate) fn should_auto_deny(matches: &[Match], rules: &Impersonation) -> bool {
+    rules.auto_deny
+        && matches
+            .iter()
+            .any(|m| m.source != Source::Player && !m.partial)
+}
+
+fn format_matches(matches: &[Match]) -> String {
+    let mut listed: Vec<String> = matches
+        .iter()
+        .take(MAX_LISTED)
+        .map(|m| m.to_string())
+        .collect();
+    if matches.len() > MAX_LISTED {
+        listed.push(format!("{} more", matches.len() - MAX_LISTED));
+    }
+    listed.join(", ")
+}
+
+/// The line flagging a request in the name approvals channel.
+pub(crate) fn format_warning(matches: &[Match]) -> String {
+    format!(
+        ":warning: **Possible impersonation:** looks like {}",
+        format_matches(matches)
+    )
+}
+
+/// What's left in the name approvals channel in place of a request that was denied automatically.
+pub(crate) fn format_auto_denied(namereq: &NameRequest, matches: &[Match]) -> String {
+    format!(
+        "{}\nDenied automatically :no_entry: (looks like {})",
+        util::format_name_request_message(namereq),
+        format_matches(matches)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skeletons() {
+        assert_eq!(skeleton("Bob"), "bob");
+        assert_eq!(skeleton(" b.O b!"), "bob");
+        assert_eq!(skeleton("B0B"), "bob");
+        // Cyrillic В and о, fullwidth b
+        assert_eq!(skeleton("\u{412}\u{43e}\u{ff42}"), "bob");
+        assert_eq!(skeleton("Admin"), skeleton("AdrnIn"));
+        assert_eq!(skeleton("Admin"), skeleton("4DM1N"));
+        assert_eq!(skeleton("Ünïcode"), "unlcode");
+        assert_ne!(skeleton("Bob"), skeleton("Rob"));
+    }
+
+    #[test]
+    fn matches() {
+        let rules = Impersonation {
+            staff_names: vec!["Dong".to_string()],
+            protected_names: vec!["Admin".to_string(), "OpenFusion".to_string()],
+            auto_deny: true,
+        };
+        let players = vec!["B0b".to_string()];
+
+        assert!(check("Robert", &players, &rules).is_empty());
+        // staff names have to look the same, protected ones only have to be in there
+        assert!(check("Dongle", &players, &rules).is_empty());
+        let matches = check("D_0_N_G", &players, &rules);
+        assert_eq!(
+            matches,
+            [Match {
+                name: "Dong".to_string(),
+                source: Source::Staff,
+                partial: false,
+            }]
+        );
+        assert!(should_auto_deny(&matches, &rules));
+        assert!(should_auto_deny(&check("4DM1N", &players, &rules), &rules));
+
+        // a protected name inside a longer one is only flagged
+        let matches = check("xXAdm1nXx", &players, &rules);
+        assert_eq!(
+            format_warning(&matches),
+            ":warning: **Possible impersonation:** looks like protected name **Admin** (in part)"
+        );
+        assert!(!should_auto_deny(&matches, &rules));
+        // including ordinary words the skeleton happens to turn it up in
+        let rules = Impersonation {
+            protected_names: vec!["Admin".to_string(), "Mod".to_string()],
+            ..rules
+        };
+        for word in ["Badminton", "Model", "Modern", "Commodore"] {
+            let matches = check(word, &players, &rules);
+            assert!(matches.iter().all(|m| m.partial), "{}", word);
+            assert!(!should_auto_deny(&matches, &rules), "{}", word);
+        }
+        assert!(should_auto_deny(&check("M0D", &players, &rules), &rules));
+
+        // other players are only flagged
+        let matches = check("Bob", &players, &rules);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].source, Source::Player);
+        assert!(!should_auto_deny(&matches, &rules));
+
+        let rules = Impersonation {
+            auto_deny: false,
+            ..rules
+        };
+        assert!(!should_auto_deny(&check("Dong", &players, &rules), &rules));
+    }
+}
@@ -1,92 +1,101 @@
+mod archive;
+mod args;
+mod config;
+mod digest;
+mod dry_run;
 mod endpoint;
+mod events;
+mod export;
+mod impersonation;
 mod monitor;
+mod namereq;
+mod paginate;
+mod permissions;
+mod preflight;
+mod recording;
+mod reload;
+mod reminders;
+mod shutdown;
+mod sink;
+mod supervisor;
+#[cfg(test)]
+mod testing;
 mod util;
 
-use std::{collections::HashSet, env::args, process::exit, sync::LazyLock, time::Duration};
+use std::{
+    env,
+    process::exit,
+    sync::{Arc, LazyLock, OnceLock, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
+use args::Args;
+use config::{Config, Settings};
 use dotenv::dotenv;
-use ffmonitor::{Monitor, NameRequestEvent};
+use ffmonitor::NameRequestEvent;
+use namereq::{Decision, Outcome};
 use poise::{
     serenity_prelude::{
-        ActivityData, ChannelId, ClientBuilder, ComponentInteraction,
-        ComponentInteractionCollector, Context, CreateActionRow, CreateAllowedMentions,
-        CreateButton, CreateInteractionResponse, CreateInteractionResponseMessage, CreateMessage,
-        GatewayIntents, GuildId, Mention, RoleId, User,
+        ButtonStyle, ClientBuilder, ComponentInteraction, ComponentInteractionCollector, Context,
+        CreateActionRow, CreateAttachment, CreateButton, CreateInteractionResponse,
+        CreateInteractionResponseMessage, EditInteractionResponse, FullEvent, GatewayIntents,
+        GuildId, Interaction, Mention, User,
     },
     CreateReply,
 };
 use regex::Regex;
-use serde::Deserialize;
-use tokio::sync::{Mutex, OnceCell};
+use serde::{Deserialize, Serialize};
+use sink::{DiscordSink, MessageRef, Sink};
+use tokio::sync::Mutex;
 
 type Error = Box<dyn std::error::Error + Send + Sync>;
 type Result<T> = std::result::Result<T, Error>;
+type PoiseContext<'a> = poise::Context<'a, Arc<Globals>, Error>;
+
+const SEARCH_PAGE_SIZE: usize = 10;
+const DEFAULT_HISTORY_COUNT: usize = 25;
+/// How long the `/namereqs` buttons keep working after the last press
+const NAMEREQS_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// How long a bulk name request decision waits to be confirmed
+const BULK_CONFIRM_TIMEOUT: Duration = Duration::from_secs(2 * 60);
+/// How often a bulk decision reports its progress, in requests
+const BULK_PROGRESS_EVERY: usize = 5;
 
 const NAME_REQUEST_PATTERN: &str = r"^Name request from Player (\d+): \*\*(.+)\*\*$";
 static NAME_REQUEST_REGEX: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(NAME_REQUEST_PATTERN).unwrap());
 
-#[derive(Debug, Deserialize)]
-struct Config {
-    guild_id: u64,
-    mod_role_id: Option<u64>,
-    mod_role_ids: Option<Vec<u64>>,
-    mod_channel_id: u64,
-    log_channel_id: u64,
-    name_approvals_channel_id: u64,
-    monitor_address: String,
-    ofapi_endpoint: String,
-}
-impl Config {
-    fn validate(&self) -> Option<&str> {
-        if self.guild_id == 0 {
-            return Some("guild_id must be set");
-        }
-        if self.mod_role_id.is_none_or(|id| id == 0)
-            && self.mod_role_ids.as_ref().is_none_or(|ids| ids.is_empty())
-        {
-            return Some("Either mod_role_id or mod_role_ids must be set");
-        }
-        if self.mod_channel_id == 0 {
-            return Some("mod_channel_id must be set");
-        }
-        None
-    }
-
-    fn get_mod_role_ids(&self) -> HashSet<RoleId> {
-        let mut set = HashSet::new();
-        if let Some(id) = self.mod_role_id {
-            set.insert(RoleId::new(id));
-        }
-        if let Some(ids) = &self.mod_role_ids {
-            for id in ids {
-                set.insert(RoleId::new(*id));
-            }
-        }
-        set
-    }
-}
-
 #[derive(Debug)]
 struct State {
     last_player_count: Option<usize>,
+    config_mtime: Option<SystemTime>,
+    digest: digest::Stats,
 }
 
 #[derive(Debug)]
 struct Globals {
     bot_user: User,
     context: Context,
-    mod_roles: HashSet<RoleId>,
-    mod_channel: ChannelId,
-    log_channel: Option<ChannelId>,
-    name_approvals_channel: Option<ChannelId>,
-    monitor_address: String,
-    ofapi_endpoint: String,
+    config_path: String,
+    guild_id: GuildId,
+    recorder: Option<Arc<recording::Recorder>>,
+    replay: Option<recording::Replay>,
+    ofapi: endpoint::OfapiClient,
+    monitor: supervisor::Supervisor,
+    archive: Arc<archive::Archive>,
+    sink: Arc<dyn Sink>,
+    shutdown: Arc<shutdown::Shutdown>,
     //
+    settings: RwLock<Arc<Settings>>,
     state: Mutex<State>,
 }
+impl Globals {
+    fn settings(&self) -> Arc<Settings> {
+        self.settings.read().unwrap().clone()
+    }
+}
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 struct NameRequest {
     player_uid: u64,
     requested_name: String,
@@ -94,7 +103,9 @@ struct NameRequest {
 impl NameRequest {
     // we can't rely on state to hold the name request, so reconstruct it from the notification we sent
     fn parse_from_notification_message(msg: &str) -> Result<NameRequest> {
-        let captures = NAME_REQUEST_REGEX.captures(msg).ok_or("Malformed")?;
+        // any context about the player comes after the first line
+        let first_line = msg.lines().next().unwrap_or_default();
+        let captures = NAME_REQUEST_REGEX.captures(first_line).ok_or("Malformed")?;
         let player_uid = captures[1].parse::<u64>()?;
         let requested_name = captures[2].to_string();
         let req = NameRequest {
@@ -113,237 +124,256 @@ impl From<NameRequestEvent> for NameRequest {
     }
 }
 
-static GLOBALS: OnceCell<Globals> = OnceCell::const_new();
-
-async fn set_listening_to(text: &str) -> Result<()> {
-    let globals = GLOBALS.get().unwrap();
-    globals
-        .context
-        .set_activity(Some(ActivityData::listening(text)));
-    Ok(())
+fn format_status(num_players: Option<usize>) -> String {
+    if let Some(num_players) = num_players {
+        if num_players == 1 {
+            "1 player".to_string()
+        } else {
+            format!("{} players", num_players)
+        }
+    } else {
+        "nothing".to_string()
+    }
 }
 
-async fn send_message(channel_id: ChannelId, message: &str) -> Result<()> {
-    let globals = GLOBALS.get().unwrap();
-    let http = &globals.context.http;
-    channel_id.say(http, message).await?;
+async fn update_status(globals: &Globals, num_players: Option<usize>) -> Result<()> {
+    let mut state = globals.state.lock().await;
+    state.last_player_count = num_players;
+
+    globals
+        .sink
+        .set_presence(&format_status(num_players))
+        .await?;
     Ok(())
 }
 
-async fn send_message_with_buttons(
-    channel_id: ChannelId,
-    message: &str,
-    buttons: Vec<CreateButton>,
+async fn reply_ephemeral(
+    globals: &Globals,
+    interaction: &ComponentInteraction,
+    content: &str,
 ) -> Result<()> {
-    let globals = GLOBALS.get().unwrap();
     let http = &globals.context.http;
-    let components = vec![CreateActionRow::Buttons(buttons)];
-    channel_id
-        .send_message(
+    interaction
+        .create_response(
             http,
-            CreateMessage::default()
-                .content(message)
-                .components(components),
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::default()
+                    .ephemeral(true)
+                    .content(content),
+            ),
         )
         .await?;
     Ok(())
 }
 
-async fn update_status(num_players: Option<usize>) -> Result<()> {
-    let globals = GLOBALS.get().unwrap();
-    let mut state = globals.state.lock().await;
-    state.last_player_count = num_players;
-
-    let text = if let Some(num_players) = num_players {
-        if num_players == 1 {
-            "1 player".to_string()
-        } else {
-            format!("{} players", num_players)
-        }
-    } else {
-        "nothing".to_string()
-    };
-    set_listening_to(&text).await?;
-    Ok(())
-}
-
-async fn handle_namereq_approve(
+async fn handle_namereq_decision(
     globals: &Globals,
     interaction: &ComponentInteraction,
+    decision: Decision,
 ) -> Result<()> {
-    let http = &globals.context.http;
-
-    let msg = interaction.message.content.clone();
+    let msg = &interaction.message.content;
+    let original = MessageRef::from(&*interaction.message);
     let user = &interaction.member.as_ref().unwrap().user;
     let by = user.tag();
 
-    let namereq = NameRequest::parse_from_notification_message(&msg)?;
-    let updated = endpoint::send_name_request_decision(globals, &namereq, "approved", &by).await?;
+    let namereq = match namereq::decide(&globals.ofapi, msg, decision, &by).await? {
+        Outcome::Decided(namereq) => namereq,
+        Outcome::AlreadyProcessed => {
+            // Try to delete the initial message
+            let _ = globals.sink.delete(original).await;
+            reply_ephemeral(globals, interaction, "Request has already been processed").await?;
+            return Ok(());
+        }
+        Outcome::Failed(msg) => {
+            reply_ephemeral(globals, interaction, &msg).await?;
+            return Ok(());
+        }
+    };
 
     // Try to delete the initial message
-    let _ = interaction.message.delete(http).await;
-
-    if !updated {
-        interaction
-            .create_response(
-                http,
-                CreateInteractionResponse::Message(
-                    CreateInteractionResponseMessage::default()
-                        .ephemeral(true)
-                        .content("Request has already been processed"),
-                ),
-            )
-            .await?;
-        return Ok(());
-    }
-
-    let Some(channel) = globals.log_channel else {
-        return Ok(());
-    };
+    let _ = globals.sink.delete(original).await;
 
-    let mention = Mention::from(user.id);
-    let content = format!(
-        "Name request from Player {} **approved** :white_check_mark: by {}: {}",
-        namereq.player_uid, mention, namereq.requested_name
-    );
-    let allowed_mentions = CreateAllowedMentions::default().empty_users(); // avoids ping
-    let msg = CreateMessage::default()
-        .content(content)
-        .allowed_mentions(allowed_mentions);
-    channel.send_message(http, msg).await?;
-    Ok(())
+    log_decision(globals, &namereq, decision, user).await
 }
 
-async fn handle_namereq_deny(globals: &Globals, interaction: &ComponentInteraction) -> Result<()> {
-    let http = &globals.context.http;
-
-    let msg = interaction.message.content.clone();
-    let user = &interaction.member.as_ref().unwrap().user;
-    let by = user.tag();
-
-    let namereq = NameRequest::parse_from_notification_message(&msg)?;
-    let updated = endpoint::send_name_request_decision(globals, &namereq, "denied", &by).await?;
-
-    // Try to delete the initial message
-    let _ = interaction.message.delete(http).await;
-
-    if !updated {
-        interaction
-            .create_response(
-                http,
-                CreateInteractionResponse::Message(
-                    CreateInteractionResponseMessage::default()
-                        .ephemeral(true)
-                        .content("Request has already been processed"),
-                ),
-            )
-            .await?;
-        return Ok(());
+/// Archives a decision, counts it towards the digest and reports it in the log channel.
+async fn log_decision(
+    globals: &Globals,
+    namereq: &NameRequest,
+    decision: Decision,
+    user: &User,
+) -> Result<()> {
+    if let Err(e) = globals.archive.record_decision(namereq, decision) {
+        println!("Error while archiving name request decision: {}", e);
     }
+    globals.state.lock().await.digest.record_decision(user.id);
 
-    let Some(channel) = globals.log_channel else {
+    let Some(channel) = globals.settings().log_channel else {
         return Ok(());
     };
 
-    let mention = Mention::from(user.id);
-    let content = format!(
-        "Name request from Player {} **denied** :no_entry: by {}: {}",
-        namereq.player_uid, mention, namereq.requested_name
-    );
-    let allowed_mentions = CreateAllowedMentions::default().empty_users(); // avoids ping
-    let msg = CreateMessage::default()
-        .content(content)
-        .allowed_mentions(allowed_mentions);
-    channel.send_message(http, msg).await?;
+    // the sink never pings, so the mention is safe
+    let content = namereq::format_log_message(namereq, decision, Mention::from(user.id));
+    globals.sink.send(channel, &content).await?;
     Ok(())
 }
 
+const STOPPING_MESSAGE: &str = "The bot is shutting down. Try again in a moment.";
+
 const ALLOWED_INTERACTIONS: [&str; 2] = ["namereq_approve", "namereq_deny"];
-const PRIVILEGED_INTERACTIONS: [&str; 2] = ["namereq_approve", "namereq_deny"];
 
 async fn handle_interaction(globals: &Globals, interaction: ComponentInteraction) -> Result<()> {
-    let http = &globals.context.http;
-
     // Check perms
     let id = interaction.data.custom_id.as_str();
     let member = interaction.member.as_ref().unwrap();
-    if PRIVILEGED_INTERACTIONS.contains(&id)
-        && !member.roles.iter().any(|r| globals.mod_roles.contains(r))
-    {
-        interaction
-            .create_response(
-                http,
-                CreateInteractionResponse::Message(
-                    CreateInteractionResponseMessage::default()
-                        .ephemeral(true)
-                        .content("You don't have permission to do that."),
-                ),
-            )
-            .await?;
+    if !permissions::check(globals, id, member) {
+        reply_ephemeral(
+            globals,
+            &interaction,
+            "You don't have permission to do that.",
+        )
+        .await?;
         return Ok(());
     }
 
     match id {
-        "namereq_approve" => handle_namereq_approve(globals, &interaction).await?,
-        "namereq_deny" => handle_namereq_deny(globals, &interaction).await?,
+        "namereq_approve" => {
+            handle_namereq_decision(globals, &interaction, Decision::Approved).await?
+        }
+        "namereq_deny" => handle_namereq_decision(globals, &interaction, Decision::Denied).await?,
         _ => return Err(format!("Unknown interaction: {}", id).into()),
     }
 
     Ok(())
 }
 
-async fn collect_interactions() {
-    wait_for_globals().await;
-    println!("Listening for interactions");
-    let globals = GLOBALS.get().unwrap();
-    loop {
-        let collector = ComponentInteractionCollector::new(globals.context.clone())
-            .filter(move |i| ALLOWED_INTERACTIONS.contains(&i.data.custom_id.as_str()));
-        let Some(interaction) = collector.next().await else {
-            println!("No interaction");
-            continue;
-        };
-        if let Err(e) = handle_interaction(globals, interaction).await {
-            println!("Error while handling interaction: {:?}", e);
-        }
+async fn event_handler(
+    _ctx: &Context,
+    event: &FullEvent,
+    _framework: poise::FrameworkContext<'_, Arc<Globals>, Error>,
+    globals: &Arc<Globals>,
+) -> Result<()> {
+    let FullEvent::InteractionCreate {
+        interaction: Interaction::Component(interaction),
+    } = event
+    else {
+        return Ok(());
+    };
+    if !ALLOWED_INTERACTIONS.contains(&interaction.data.custom_id.as_str()) {
+        return Ok(());
     }
-}
-
-async fn wait_for_globals() {
-    while GLOBALS.get().is_none() {
-        tokio::time::sleep(Duration::from_millis(100)).await;
+    let Some(_task) = globals.shutdown.begin() else {
+        reply_ephemeral(globals, interaction, STOPPING_MESSAGE).await?;
+        return Ok(());
+    };
+    if let Err(e) = handle_interaction(globals, interaction.clone()).await {
+        println!("Error while handling interaction: {:?}", e);
     }
+    Ok(())
 }
 
-async fn on_init() -> Result<()> {
-    let globals = GLOBALS.get().unwrap();
-
+async fn on_init(globals: Arc<Globals>) -> Result<()> {
     let bot_user = &globals.bot_user;
     println!("Logged in as {} ({})", bot_user.tag(), bot_user.id);
 
-    send_message(globals.mod_channel, "Bot started").await?;
-    update_status(None).await?;
+    let report = preflight::run(&globals).await;
+    println!("Preflight checks:\n{}", report);
 
-    // start ffmonitor
-    let rt = tokio::runtime::Handle::current();
-    let callback = move |notification| {
-        rt.spawn(async move {
-            if let Err(e) = monitor::handle_notification(notification).await {
-                println!("Error while handling monitor event: {:?}", e);
+    let mod_channel = globals.settings().mod_channel;
+    globals.sink.send(mod_channel, "Bot started").await?;
+    if report.num_problems() > 0 {
+        let msg = format!("Preflight checks found problems:\n{}", report);
+        globals.sink.send(mod_channel, &msg).await?;
+        let alert = format!(
+            "Preflight checks found {} problem(s)",
+            report.num_problems()
+        );
+        globals
+            .state
+            .lock()
+            .await
+            .digest
+            .record_alert(&alert, SystemTime::now());
+    }
+    update_status(&globals, None).await?;
+
+    // watch for config changes
+    tokio::spawn(reload::watch_config_file(globals.clone()));
+    #[cfg(unix)]
+    tokio::spawn(reload::watch_sighup(globals.clone()));
+
+    tokio::spawn(digest::run(globals.clone()));
+    tokio::spawn(reminders::run(globals.clone()));
+    tokio::spawn(archive::prune_periodically(globals.clone()));
+
+    // replay a recording in place of the monitor, if asked to
+    if let Some(replay) = globals.replay.clone() {
+        println!(
+            "Replaying {} instead of connecting to the monitor",
+            replay.path
+        );
+        tokio::spawn(async move {
+            let handle = |notification, at| {
+                let task = globals.shutdown.begin();
+                let globals = &globals;
+                async move {
+                    let Some(_task) = task else {
+                        return Ok(());
+                    };
+                    monitor::handle_notification(globals, notification, at, true).await
+                }
+            };
+            match recording::replay(&replay, handle).await {
+                Ok(()) => println!("Replay finished"),
+                Err(e) => println!("Error while replaying: {}", e),
             }
         });
-    };
-    if let Err(e) = Monitor::new_with_callback(&globals.monitor_address, Box::new(callback)) {
-        return Err(format!("Error preparing ffmonitor: {:?}", e).into());
+        return Ok(());
     }
 
+    tokio::spawn(supervisor::run(globals.clone()));
+
     Ok(())
 }
 
+/// Runs before every slash command, so permissions are enforced in one place
+async fn command_check(ctx: PoiseContext<'_>) -> Result<bool> {
+    let globals = ctx.data();
+    // subcommands share their parent's permission
+    let name = match ctx.parent_commands().first() {
+        Some(parent) => &parent.name,
+        None => &ctx.command().name,
+    };
+    if globals.shutdown.is_stopping() {
+        let reply = CreateReply::default()
+            .content(STOPPING_MESSAGE)
+            .reply(true)
+            .ephemeral(true);
+        if let Err(e) = ctx.send(reply).await {
+            println!("Failed to reply to /{}: {}", name, e);
+        }
+        return Ok(false);
+    }
+    let allowed = match ctx.author_member().await {
+        Some(member) => permissions::check(globals, name, &member),
+        None => false,
+    };
+    if !allowed {
+        let reply = CreateReply::default()
+            .content("You don't have permission to do that.")
+            .reply(true)
+            .ephemeral(true);
+        if let Err(e) = ctx.send(reply).await {
+            println!("Failed to reply to /{}: {}", name, e);
+        }
+    }
+    Ok(allowed)
+}
+
 /// Check the status of the server
 #[poise::command(slash_command)]
-async fn check(ctx: poise::Context<'_, (), Error>) -> Result<()> {
-    let globals = GLOBALS.get().unwrap();
+async fn check(ctx: PoiseContext<'_>) -> Result<()> {
+    let globals = ctx.data();
     let state = globals.state.lock().await;
     let msg = match state.last_player_count {
         Some(num_players) => {
@@ -362,40 +392,635 @@ async fn check(ctx: poise::Context<'_, (), Error>) -> Result<()> {
     Ok(())
 }
 
-/// Get all outstanding name requests
+fn namereq_buttons(prefix: &str, reqs: &[NameRequest], page: usize) -> Vec<CreateActionRow> {
+    let mut rows: Vec<CreateActionRow> = reqs
+        .iter()
+        .enumerate()
+        .skip(page * namereq::PAGE_SIZE)
+        .take(namereq::PAGE_SIZE)
+        .map(|(i, _)| {
+            CreateActionRow::Buttons(vec![
+                CreateButton::new(format!("{}:approve:{}", prefix, i))
+                    .label(format!("Approve {}", i + 1))
+                    .style(ButtonStyle::Success),
+                CreateButton::new(format!("{}:deny:{}", prefix, i))
+                    .label(format!("Deny {}", i + 1))
+                    .style(ButtonStyle::Danger),
+            ])
+        })
+        .collect();
+    let num_pages = namereq::num_pages(reqs.len());
+    rows.push(CreateActionRow::Buttons(vec![
+        CreateButton::new(format!("{}:prev", prefix))
+            .label("Previous")
+            .style(ButtonStyle::Secondary)
+            .disabled(page == 0),
+        CreateButton::new(format!("{}:refresh", prefix))
+            .label("Refresh")
+            .style(ButtonStyle::Secondary),
+        CreateButton::new(format!("{}:next", prefix))
+            .label("Next")
+            .style(ButtonStyle::Secondary)
+            .disabled(page + 1 >= num_pages),
+    ]));
+    rows
+}
+
+/// Approves or denies a request from the `/namereqs` view, returning a note on how it went.
+async fn decide_from_view(
+    globals: &Globals,
+    namereq: NameRequest,
+    decision: Decision,
+    user: &User,
+) -> String {
+    let Some(_task) = globals.shutdown.begin() else {
+        return STOPPING_MESSAGE.to_string();
+    };
+    match namereq::decide_request(&globals.ofapi, namereq.clone(), decision, &user.tag()).await {
+        Outcome::Decided(namereq) => {
+            delete_relayed_namereq(globals, &namereq).await;
+            if let Err(e) = log_decision(globals, &namereq, decision, user).await {
+                println!("Failed to log name request decision: {}", e);
+            }
+            namereq::format_log_message(&namereq, decision, Mention::from(user.id))
+        }
+        Outcome::AlreadyProcessed => {
+            delete_relayed_namereq(globals, &namereq).await;
+            format!(
+                "The request from Player {} had already been processed",
+                namereq.player_uid
+            )
+        }
+        Outcome::Failed(msg) => msg,
+    }
+}
+
+/// Deletes the approvals channel message `namereq` was relayed as, going by the archive,
+/// like pressing its buttons would.
+async fn delete_relayed_namereq(globals: &Globals, namereq: &NameRequest) {
+    if let Some(message) = globals.archive.name_request_message(namereq) {
+        // it may have been deleted already
+        let _ = globals.sink.delete(message).await;
+    }
+}
+
+/// The outstanding name requests that match `filter`.
+async fn get_namereqs(
+    globals: &Globals,
+    filter: &namereq::Filter,
+) -> std::result::Result<Vec<NameRequest>, endpoint::OfapiError> {
+    let reqs = endpoint::get_outstanding_namereqs(&globals.ofapi).await?;
+    let reqs = reqs
+        .into_iter()
+        .filter(|req| filter.matches(req, globals.archive.name_request_seen(req)))
+        .collect();
+    Ok(reqs)
+}
+
+async fn say_ephemeral(ctx: PoiseContext<'_>, content: impl Into<String>) -> Result<()> {
+    let reply = CreateReply::default()
+        .content(content)
+        .reply(true)
+        .ephemeral(true);
+    ctx.send(reply).await?;
+    Ok(())
+}
+
+/// Browse outstanding name requests and approve or deny them, one by one or in bulk
 #[poise::command(slash_command)]
-async fn namereqs(ctx: poise::Context<'_, (), Error>) -> Result<()> {
-    let globals = GLOBALS.get().unwrap();
+async fn namereqs(
+    ctx: PoiseContext<'_>,
+    #[description = "Only names matching this regular expression"] name: Option<String>,
+    #[description = "Only players with at least this UID"] min_uid: Option<u64>,
+    #[description = "Only players with at most this UID"] max_uid: Option<u64>,
+    #[description = "Only requests older than this, e.g. 2h or 3d"] older_than: Option<String>,
+    #[description = "Approve or deny every matching request, after confirming"] bulk: Option<
+        Decision,
+    >,
+) -> Result<()> {
+    let globals = ctx.data();
 
-    // check moderator role
-    let member = ctx.author_member().await.unwrap();
-    if !member.roles.iter().any(|r| globals.mod_roles.contains(r)) {
-        let reply = CreateReply::default()
-            .content("You don't have permission to do that.")
-            .reply(true)
-            .ephemeral(true);
-        if let Err(e) = ctx.send(reply).await {
-            println!("Failed to reply to /namereqs: {}", e);
+    let name = match name.map(|name| Regex::new(&name)).transpose() {
+        Ok(name) => name,
+        Err(e) => return say_ephemeral(ctx, format!("Invalid name pattern: {}", e)).await,
+    };
+    let now = SystemTime::now();
+    let seen_before = match older_than.map(|t| util::parse_time(&t, now)).transpose() {
+        Ok(seen_before) => seen_before,
+        Err(e) => return say_ephemeral(ctx, e.to_string()).await,
+    };
+    let filter = namereq::Filter {
+        name,
+        min_uid,
+        max_uid,
+        seen_before,
+    };
+
+    if let Some(decision) = bulk {
+        let allowed = match ctx.author_member().await {
+            Some(member) => permissions::check(globals, decision.action(), &member),
+            None => false,
+        };
+        if !allowed {
+            return say_ephemeral(ctx, "You don't have permission to do that.").await;
         }
+    }
+
+    ctx.defer_ephemeral().await?;
+    let reqs = match get_namereqs(globals, &filter).await {
+        Ok(reqs) => reqs,
+        Err(e) => {
+            println!("Failed to get outstanding name requests: {}", e);
+            let msg = format!(
+                "Couldn't get outstanding name requests. {}",
+                e.user_message()
+            );
+            return say_ephemeral(ctx, msg).await;
+        }
+    };
+
+    match bulk {
+        Some(decision) => bulk_decide(ctx, reqs, decision, filter).await,
+        None => browse_namereqs(ctx, reqs, filter).await,
+    }
+}
+
+/// Asks for confirmation, then makes the same decision on every request in `reqs`.
+async fn bulk_decide(
+    ctx: PoiseContext<'_>,
+    reqs: Vec<NameRequest>,
+    decision: Decision,
+    filter: namereq::Filter,
+) -> Result<()> {
+    let globals = ctx.data();
+    if reqs.is_empty() {
+        return say_ephemeral(ctx, "No outstanding name requests match").await;
+    }
+
+    let confirm_id = format!("{}:confirm", ctx.id());
+    let cancel_id = format!("{}:cancel", ctx.id());
+    let style = match decision {
+        Decision::Approved => ButtonStyle::Success,
+        Decision::Denied => ButtonStyle::Danger,
+    };
+    let buttons = vec![CreateActionRow::Buttons(vec![
+        CreateButton::new(&confirm_id).label("Confirm").style(style),
+        CreateButton::new(&cancel_id)
+            .label("Cancel")
+            .style(ButtonStyle::Secondary),
+    ])];
+    let reply = CreateReply::default()
+        .content(namereq::format_bulk_summary(&reqs, decision, &filter))
+        .components(buttons)
+        .reply(true)
+        .ephemeral(true);
+    let handle = ctx.send(reply).await?;
+
+    let press = ComponentInteractionCollector::new(ctx)
+        .author_id(ctx.author().id)
+        .custom_ids(vec![confirm_id.clone(), cancel_id])
+        .timeout(BULK_CONFIRM_TIMEOUT)
+        .await;
+    let press = match press {
+        Some(press) if press.data.custom_id == confirm_id => press,
+        Some(press) => {
+            let response = CreateInteractionResponseMessage::new()
+                .content("Cancelled; nothing was changed")
+                .components(vec![]);
+            press
+                .create_response(ctx, CreateInteractionResponse::UpdateMessage(response))
+                .await?;
+            return Ok(());
+        }
+        None => {
+            let reply = CreateReply::default()
+                .content("Timed out; nothing was changed")
+                .components(vec![]);
+            handle.edit(ctx, reply).await?;
+            return Ok(());
+        }
+    };
+
+    press.defer(ctx).await?;
+    let Some(_task) = globals.shutdown.begin() else {
+        let edit = EditInteractionResponse::new()
+            .content(STOPPING_MESSAGE)
+            .components(vec![]);
+        press.edit_response(ctx, edit).await?;
         return Ok(());
+    };
+    let user = &press.user;
+    let by = user.tag();
+    println!(
+        "{} is bulk {} {} name requests ({})",
+        by,
+        decision.as_str(),
+        reqs.len(),
+        filter
+    );
+
+    let total = reqs.len();
+    let mut result = namereq::BulkResult::default();
+    for req in reqs {
+        // leave the rest for after the restart
+        if globals.shutdown.is_stopping() {
+            break;
+        }
+        let outcome = namereq::decide_request(&globals.ofapi, req.clone(), decision, &by).await;
+        if matches!(outcome, Outcome::Decided(_) | Outcome::AlreadyProcessed) {
+            delete_relayed_namereq(globals, &req).await;
+        }
+        if let Outcome::Decided(namereq) = &outcome {
+            if let Err(e) = globals.archive.record_decision(namereq, decision) {
+                println!("Error while archiving name request decision: {}", e);
+            }
+            let mut state = globals.state.lock().await;
+            state.digest.record_decision(user.id);
+        }
+        result.record(outcome);
+        if result.num_done() % BULK_PROGRESS_EVERY == 0 && result.num_done() < total {
+            let edit = EditInteractionResponse::new()
+                .content(result.format_progress(decision, total))
+                .components(vec![]);
+            if let Err(e) = press.edit_response(ctx, edit).await {
+                println!("Failed to report bulk decision progress: {}", e);
+            }
+        }
+    }
+
+    if let Some(channel) = globals.settings().log_channel {
+        // the sink never pings, so the mention is safe
+        let content = result.format_log_message(decision, &filter, Mention::from(user.id));
+        if let Err(e) = globals.sink.send(channel, &content).await {
+            println!("Failed to log bulk decision: {}", e);
+        }
+    }
+
+    let mut msg = format!("Done. {}", result.format_progress(decision, total));
+    if result.num_done() < total {
+        msg.push_str(&format!("\n{}", STOPPING_MESSAGE));
+    }
+    let edit = EditInteractionResponse::new()
+        .content(msg)
+        .components(vec![]);
+    press.edit_response(ctx, edit).await?;
+    Ok(())
+}
+
+/// Shows `reqs` a page at a time, with buttons to approve or deny each one.
+async fn browse_namereqs(
+    ctx: PoiseContext<'_>,
+    mut reqs: Vec<NameRequest>,
+    filter: namereq::Filter,
+) -> Result<()> {
+    let globals = ctx.data();
+
+    let prefix = ctx.id().to_string();
+    let mut page = 0;
+    let reply = CreateReply::default()
+        .content(namereq::format_page(&reqs, &filter, page, None))
+        .components(namereq_buttons(&prefix, &reqs, page))
+        .reply(true)
+        .ephemeral(true);
+    let handle = ctx.send(reply).await?;
+
+    let id_prefix = format!("{}:", prefix);
+    loop {
+        let filter_prefix = id_prefix.clone();
+        let Some(press) = ComponentInteractionCollector::new(ctx)
+            .filter(move |press| press.data.custom_id.starts_with(&filter_prefix))
+            .timeout(NAMEREQS_TIMEOUT)
+            .await
+        else {
+            break;
+        };
+        let action = &press.data.custom_id[id_prefix.len()..];
+        let decision = match action.split_once(':') {
+            Some(("approve", i)) => i.parse::<usize>().ok().map(|i| (Decision::Approved, i)),
+            Some(("deny", i)) => i.parse::<usize>().ok().map(|i| (Decision::Denied, i)),
+            _ => None,
+        };
+
+        if let Some((decision, _)) = decision {
+            let allowed = press
+                .member
+                .as_ref()
+                .is_some_and(|member| permissions::check(globals, decision.action(), member));
+            if !allowed {
+                reply_ephemeral(globals, &press, "You don't have permission to do that.").await?;
+                continue;
+            }
+        }
+
+        // decisions and refreshes go to OFAPI, which can take a while
+        press.defer(ctx).await?;
+        let mut note = None;
+        match (action, decision) {
+            ("prev", _) => page = page.saturating_sub(1),
+            ("next", _) => page += 1,
+            (_, Some((decision, i))) => {
+                note = Some(match reqs.get(i).cloned() {
+                    Some(namereq) => {
+                        decide_from_view(globals, namereq, decision, &press.user).await
+                    }
+                    None => "That request is no longer listed".to_string(),
+                });
+            }
+            _ => {}
+        }
+        if action == "refresh" || decision.is_some() {
+            match get_namereqs(globals, &filter).await {
+                Ok(new_reqs) => reqs = new_reqs,
+                Err(e) => {
+                    println!("Failed to refresh outstanding name requests: {}", e);
+                    let msg = format!("Couldn't refresh the list. {}", e.user_message());
+                    note = Some(match note {
+                        Some(note) => format!("{}\n> {}", note, msg),
+                        None => msg,
+                    });
+                }
+            }
+        }
+        page = page.min(namereq::num_pages(reqs.len()) - 1);
+
+        let edit = EditInteractionResponse::new()
+            .content(namereq::format_page(&reqs, &filter, page, note.as_deref()))
+            .components(namereq_buttons(&prefix, &reqs, page));
+        press.edit_response(ctx, edit).await?;
     }
 
-    let reqs = endpoint::get_outstanding_namereqs(globals).await?;
+    // the buttons won't do anything any more
+    let reply = CreateReply::default().components(vec![]);
+    let _ = handle.edit(ctx, reply).await;
+    Ok(())
+}
 
-    let msg = format!("Found {} outstanding requests", reqs.len());
+/// Reload the config file
+#[poise::command(slash_command)]
+async fn reload(ctx: PoiseContext<'_>) -> Result<()> {
+    let globals = ctx.data();
+
+    let trigger = format!("/reload by {}", ctx.author().tag());
+    let msg = match reload::reload_config(globals, &trigger).await {
+        Ok(changes) => format!("Config reloaded with {} change(s)", changes.len()),
+        Err(e) => format!("Config reload failed: {}", e),
+    };
     let reply = CreateReply::default()
         .content(msg)
         .reply(true)
         .ephemeral(true);
     if let Err(e) = ctx.send(reply).await {
-        println!("Failed to reply to /namereqs: {}", e);
+        println!("Failed to reply to /reload: {}", e);
     }
+    Ok(())
+}
 
-    let channel = ctx.channel_id();
-    for req in reqs {
-        if let Err(e) = util::send_name_request_message(channel, &req).await {
-            println!("Failed to send name request message: {}", e);
+/// Check the bot's configuration and permissions
+#[poise::command(slash_command)]
+async fn diagnose(ctx: PoiseContext<'_>) -> Result<()> {
+    let globals = ctx.data();
+
+    ctx.defer_ephemeral().await?;
+    let report = preflight::run(globals).await;
+    let reply = CreateReply::default()
+        .content(report.to_string())
+        .reply(true)
+        .ephemeral(true);
+    if let Err(e) = ctx.send(reply).await {
+        println!("Failed to reply to /diagnose: {}", e);
+    }
+    Ok(())
+}
+
+/// Show or control the connection to the game server's monitor
+#[poise::command(
+    slash_command,
+    rename = "monitor",
+    subcommands("monitor_status", "monitor_reconnect", "monitor_address"),
+    subcommand_required
+)]
+async fn monitor_command(_ctx: PoiseContext<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Replies to a /monitor subcommand, or explains that there's no monitor connection to control.
+async fn reply_monitor(ctx: PoiseContext<'_>, act: impl FnOnce(&Globals) -> String) -> Result<()> {
+    let globals = ctx.data();
+    let msg = match &globals.replay {
+        Some(replay) => format!(
+            "Replaying `{}`, so there's no monitor connection",
+            replay.path
+        ),
+        None => act(globals),
+    };
+    let reply = CreateReply::default()
+        .content(msg)
+        .reply(true)
+        .ephemeral(true);
+    if let Err(e) = ctx.send(reply).await {
+        println!("Failed to reply to /monitor: {}", e);
+    }
+    Ok(())
+}
+
+/// Show the monitor connection status
+#[poise::command(slash_command, rename = "status")]
+async fn monitor_status(ctx: PoiseContext<'_>) -> Result<()> {
+    reply_monitor(ctx, |globals| globals.monitor.status()).await
+}
+
+/// Drop the monitor connection and connect again
+#[poise::command(slash_command, rename = "reconnect")]
+async fn monitor_reconnect(ctx: PoiseContext<'_>) -> Result<()> {
+    println!("Monitor reconnect requested by {}", ctx.author().tag());
+    reply_monitor(ctx, |globals| {
+        globals.monitor.reconnect(None);
+        "Reconnecting to the monitor".to_string()
+    })
+    .await
+}
+
+/// Connect to a different monitor until the bot restarts
+#[poise::command(slash_command, rename = "address")]
+async fn monitor_address(
+    ctx: PoiseContext<'_>,
+    #[description = "Monitor address, e.g. 127.0.0.1:8003"] address: String,
+) -> Result<()> {
+    println!(
+        "Monitor address changed to {} by {}",
+        address,
+        ctx.author().tag()
+    );
+    reply_monitor(ctx, |globals| {
+        let msg = format!("Switching the monitor to `{}`", address);
+        globals.monitor.reconnect(Some(address));
+        msg
+    })
+    .await
+}
+
+/// Search the archive of chat, emails and broadcasts
+#[poise::command(slash_command)]
+async fn search(
+    ctx: PoiseContext<'_>,
+    #[description = "Who sent it"] player: Option<String>,
+    #[description = "Who it was sent to"] recipient: Option<String>,
+    #[description = "What kind of event"] kind: Option<archive::Kind>,
+    #[description = "Text in the message"] text: Option<String>,
+    #[description = "Only after this, e.g. 2h, 7d or 2024-05-01 18:30 (UTC)"] since: Option<String>,
+    #[description = "Only before this, e.g. 1h or 2024-05-02"] until: Option<String>,
+) -> Result<()> {
+    let globals = ctx.data();
+
+    let now = SystemTime::now();
+    let parse = |time: Option<String>| time.map(|t| util::parse_time(&t, now)).transpose();
+    let (since, until) = match (parse(since), parse(until)) {
+        (Ok(since), Ok(until)) => (since, until),
+        (Err(e), _) | (_, Err(e)) => {
+            let reply = CreateReply::default()
+                .content(e.to_string())
+                .reply(true)
+                .ephemeral(true);
+            ctx.send(reply).await?;
+            return Ok(());
+        }
+    };
+    let query = archive::Query {
+        player,
+        recipient,
+        kind,
+        text,
+        since,
+        until,
+        ..Default::default()
+    };
+
+    let results = globals.archive.search(&query);
+    let lines: Vec<String> = results
+        .iter()
+        .map(|record| record.format(globals.guild_id))
+        .collect();
+    let header = match results.len() {
+        0 => "No matching events".to_string(),
+        1 => "Found **1** matching event".to_string(),
+        n => format!("Found **{}** matching events, newest first", n),
+    };
+    paginate::paginate(ctx, paginate::into_pages(&header, &lines, SEARCH_PAGE_SIZE)).await
+}
+
+/// Show a player's recent chat, sent emails and name requests
+#[poise::command(slash_command)]
+async fn history(
+    ctx: PoiseContext<'_>,
+    #[description = "Player name, or UID for name requests"] player: String,
+    #[description = "How many events to show (default 25)"]
+    #[min = 1]
+    #[max = 200]
+    count: Option<usize>,
+    #[description = "Only this far back, e.g. 2h or 7d"] within: Option<String>,
+) -> Result<()> {
+    let globals = ctx.data();
+
+    let since = match within.map(|t| util::parse_time(&t, SystemTime::now())) {
+        Some(Ok(since)) => Some(since),
+        Some(Err(e)) => {
+            let reply = CreateReply::default()
+                .content(e.to_string())
+                .reply(true)
+                .ephemeral(true);
+            ctx.send(reply).await?;
+            return Ok(());
+        }
+        None => None,
+    };
+    let count = count.unwrap_or(DEFAULT_HISTORY_COUNT);
+
+    let records = globals.archive.history(&player, since, count);
+    let lines: Vec<String> = records
+        .iter()
+        .map(|record| record.format(globals.guild_id))
+        .collect();
+    let header = match records.len() {
+        0 => format!("No recent activity for **{}**", player),
+        n => format!("Last **{}** event(s) for **{}**, oldest first", n, player),
+    };
+    paginate::paginate(ctx, paginate::into_pages(&header, &lines, SEARCH_PAGE_SIZE)).await
+}
+
+/// Download archived chat, emails and broadcasts as a file
+#[poise::command(slash_command, rename = "export")]
+async fn export_command(
+    ctx: PoiseContext<'_>,
+    #[description = "File format (default JSON Lines)"] format: Option<export::Format>,
+    #[description = "Only after this, e.g. 2h, 7d or 2024-05-01 18:30 (UTC)"] since: Option<String>,
+    #[description = "Only before this, e.g. 1h or 2024-05-02"] until: Option<String>,
+    #[description = "Only events sent by or to this player"] player: Option<String>,
+) -> Result<()> {
+    let globals = ctx.data();
+
+    let now = SystemTime::now();
+    let parse = |time: Option<String>| time.map(|t| util::parse_time(&t, now)).transpose();
+    let (since, until) = match (parse(since), parse(until)) {
+        (Ok(since), Ok(until)) => (since, until),
+        (Err(e), _) | (_, Err(e)) => {
+            let reply = CreateReply::default()
+                .content(e.to_string())
+                .reply(true)
+                .ephemeral(true);
+            ctx.send(reply).await?;
+            return Ok(());
         }
+    };
+    let query = archive::Query {
+        involving: player,
+        since,
+        until,
+        ..Default::default()
+    };
+
+    ctx.defer_ephemeral().await?;
+    let mut records = globals.archive.search(&query);
+    if records.is_empty() {
+        let reply = CreateReply::default()
+            .content("No matching events to export")
+            .ephemeral(true);
+        ctx.send(reply).await?;
+        return Ok(());
+    }
+    records.reverse();
+
+    let secs = now
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let name = format!("computress-export-{}", secs);
+    let format = format.unwrap_or_default();
+    let num_records = records.len();
+    // compressing a big export takes a while
+    let files = tokio::task::spawn_blocking(move || {
+        export::export(&records, format, &name, export::MAX_UPLOAD_SIZE)
+    })
+    .await??;
+    println!(
+        "{} exported {} events in {} file(s)",
+        ctx.author().tag(),
+        num_records,
+        files.len()
+    );
+
+    let msg = format!("Exported **{}** events, oldest first", num_records);
+    for (i, files) in export::batch(files, export::MAX_UPLOAD_SIZE)
+        .into_iter()
+        .enumerate()
+    {
+        let mut reply = CreateReply::default().ephemeral(true);
+        if i == 0 {
+            reply = reply.content(&msg);
+        }
+        for file in files {
+            reply = reply.attachment(CreateAttachment::bytes(file.data, file.name));
+        }
+        ctx.send(reply).await?;
     }
     Ok(())
 }
@@ -414,38 +1039,145 @@ async fn main() {
         println!("Loaded .env");
     }
 
-    // Load, parse, and validate config
-    let config_file_path = args().nth(1).unwrap_or("config.json".to_string());
-    let Ok(config_file_contents) = std::fs::read_to_string(&config_file_path) else {
-        println!("Config file missing: {}", config_file_path);
-        exit(1);
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            println!("{}\n{}", e, args::USAGE);
+            exit(1);
+        }
     };
-    let config: Config = match serde_json::from_str(&config_file_contents) {
+
+    // Load, parse, and validate config
+    let config_file_path = args.config_path;
+    let config = match Config::load(&config_file_path) {
         Ok(config) => config,
         Err(e) => {
-            println!("Error while parsing config.json: {:?}", e);
+            println!("{}", e);
             exit(1);
         }
     };
-    if let Some(e) = config.validate() {
-        println!("Invalid config: {}", e);
-        exit(1);
-    }
     println!("Loaded config: {}", config_file_path);
 
-    let Ok(token) = std::env::var("DISCORD_TOKEN") else {
-        println!("DISCORD_TOKEN environment variable missing");
-        exit(1);
+    let ofapi_auth = match endpoint::OfapiAuth::load(&config) {
+        Ok(auth) => auth,
+        Err(e) => {
+            println!("Couldn't load OFAPI credentials: {}", e);
+            exit(1);
+        }
+    };
+    if ofapi_auth.is_empty() {
+        println!("Warning: no OFAPI token or login configured; name request decisions won't work");
+    }
+    let ofapi = match endpoint::OfapiOptions::from_config(&config)
+        .and_then(|options| endpoint::OfapiClient::new(options, ofapi_auth))
+    {
+        Ok(ofapi) => ofapi,
+        Err(e) => {
+            println!("Couldn't set up OFAPI client: {}", e);
+            exit(1);
+        }
+    };
+
+    let recorder = match &args.record_dir {
+        Some(dir) => match recording::Recorder::create(dir) {
+            Ok(recorder) => {
+                println!(
+                    "Recording monitor notifications to {}",
+                    recorder.path().display()
+                );
+                Some(Arc::new(recorder))
+            }
+            Err(e) => {
+                println!("Couldn't start recording: {}", e);
+                exit(1);
+            }
+        },
+        None => None,
+    };
+    let replay = args.replay_path.map(|path| recording::Replay {
+        path,
+        speed: args.replay_speed,
+    });
+
+    if args.dry_run {
+        let settings = Settings::from(&config);
+        let dry_run = match dry_run::DryRun::new(args.dry_run_output.as_deref(), settings) {
+            Ok(dry_run) => Arc::new(dry_run),
+            Err(e) => {
+                println!("{}", e);
+                exit(1);
+            }
+        };
+        println!("Dry run: nothing will be posted to Discord, and OFAPI decisions will be printed instead of sent");
+        if let Err(e) =
+            dry_run::run(dry_run, &config.monitor_address, ofapi, recorder, replay).await
+        {
+            println!("{}", e);
+            exit(1);
+        }
+        return;
+    }
+
+    let token = match util::get_secret("DISCORD_TOKEN") {
+        Ok(Some(token)) => token,
+        Ok(None) => {
+            println!("DISCORD_TOKEN environment variable missing");
+            exit(1);
+        }
+        Err(e) => {
+            println!("{}", e);
+            exit(1);
+        }
     };
 
+    let retention = config.get_archive_retention();
+    let archive = match archive::Archive::open(config.get_archive_path(), retention) {
+        Ok(archive) => {
+            println!(
+                "Loaded archive: {} ({} events)",
+                archive.path().display(),
+                archive.len()
+            );
+            archive
+        }
+        Err(e) => {
+            println!("{}", e);
+            exit(1);
+        }
+    };
+
+    let archive = Arc::new(archive);
+    let shutdown = Arc::new(shutdown::Shutdown::default());
+    let framework_shutdown = shutdown.clone();
+    // set once the bot is ready, for the signal handler
+    let ready = Arc::new(OnceLock::new());
+    let framework_ready = ready.clone();
+    let framework_archive = archive.clone();
+
     let intents = GatewayIntents::non_privileged();
-    let commands = vec![check(), namereqs()];
-    let framework: poise::Framework<(), Error> = poise::Framework::builder()
+    let commands = vec![
+        check(),
+        namereqs(),
+        reload(),
+        diagnose(),
+        monitor_command(),
+        search(),
+        history(),
+        export_command(),
+    ];
+    let framework: poise::Framework<Arc<Globals>, Error> = poise::Framework::builder()
         .options(poise::FrameworkOptions {
             commands,
+            command_check: Some(|ctx| Box::pin(command_check(ctx))),
+            event_handler: |ctx, event, framework, globals| {
+                Box::pin(event_handler(ctx, event, framework, globals))
+            },
             ..Default::default()
         })
         .setup(move |ctx, _ready, framework| {
+            let shutdown = framework_shutdown;
+            let ready = framework_ready;
+            let archive = framework_archive;
             Box::pin(async move {
                 // Deregister any previously set global commands
                 let empty_global_commands: Vec<poise::Command<(), ()>> = vec![];
@@ -470,36 +1202,37 @@ async fn main() {
 
                 let state = State {
                     last_player_count: None,
+                    config_mtime: reload::get_mtime(&config_file_path),
+                    digest: digest::Stats::new(SystemTime::now()),
                 };
 
-                GLOBALS
-                    .set(Globals {
-                        bot_user,
-                        context: ctx.clone(),
-                        mod_roles: config.get_mod_role_ids(),
-                        mod_channel: ChannelId::new(config.mod_channel_id),
-                        log_channel: if config.log_channel_id != 0 {
-                            Some(ChannelId::new(config.log_channel_id))
-                        } else {
-                            None
-                        },
-                        name_approvals_channel: if config.name_approvals_channel_id != 0 {
-                            Some(ChannelId::new(config.name_approvals_channel_id))
-                        } else {
-                            None
-                        },
-                        monitor_address: config.monitor_address,
-                        ofapi_endpoint: config.ofapi_endpoint,
-                        //
-                        state: Mutex::new(state),
-                    })
-                    .unwrap();
-
-                if let Err(e) = on_init().await {
+                let globals = Arc::new(Globals {
+                    bot_user,
+                    context: ctx.clone(),
+                    config_path: config_file_path,
+                    guild_id,
+                    settings: RwLock::new(Arc::new(Settings::from(&config))),
+                    monitor: supervisor::Supervisor::new(&config.monitor_address),
+                    archive,
+                    recorder,
+                    replay,
+                    ofapi,
+                    sink: Arc::new(DiscordSink::new(ctx.clone())),
+                    shutdown: shutdown.clone(),
+                    //
+                    state: Mutex::new(state),
+                });
+
+                let _ = ready.set(globals.clone());
+                // stopped before getting this far, so don't start anything
+                if shutdown.is_stopping() {
+                    return Ok(globals);
+                }
+                if let Err(e) = on_init(globals.clone()).await {
                     println!("Error while initializing: {:?}", e);
                     exit(1);
                 }
-                Ok(())
+                Ok(globals)
             })
         })
         .build();
@@ -515,10 +1248,20 @@ async fn main() {
         }
     };
 
-    tokio::spawn(collect_interactions());
+    // listen for signals before connecting, so stopping early still exits cleanly
+    tokio::spawn(shutdown::watch_signals(
+        shutdown.clone(),
+        archive,
+        ready,
+        client.shard_manager.clone(),
+    ));
 
     let res = client.start().await;
     if let Err(e) = res {
         println!("Client error: {:?}", e);
+        exit(shutdown::EXIT_ERROR);
     }
+    let code = shutdown.exit_code();
+    println!("Stopped");
+    exit(code);
 }
@@ -1,16 +1,55 @@
-use ffmonitor::{
-    BroadcastEvent, ChatEvent, EmailEvent, Event, MonitorNotification, MonitorUpdate,
-    NameRequestEvent,
+use std::time::SystemTime;
+
+use poise::serenity_prelude::ChannelId;
+
+use crate::{
+    archive::Archive,
+    config::{ChannelFormats, MessageFormat, Settings},
+    endpoint::OfapiClient,
+    events::{BroadcastEvent, ChatEvent, EmailEvent, Event, Notification},
+    format_status, impersonation,
+    namereq::{self, Decision, Outcome},
+    sink::{Embed, MessageRef, Sink},
+    util, Globals, NameRequest, Result,
 };
 
-use crate::{send_message, update_status, util, Globals, NameRequest, Result, GLOBALS};
+/// Something the bot should do in response to a monitor notification.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Output {
+    /// Show the player count (or that the server is offline) in the bot's status
+    Status(Option<usize>),
+    Message {
+        channel: ChannelId,
+        content: String,
+    },
+    Embed {
+        channel: ChannelId,
+        embed: Embed,
+    },
+    /// Post a name request with approve/deny buttons
+    NameRequest {
+        channel: ChannelId,
+        name_request: NameRequest,
+        /// Lines about the player, filled in from the archive when there is one
+        context: Vec<String>,
+    },
+}
 
-async fn handle_chat_event(globals: &Globals, chat: ChatEvent) -> Result<()> {
-    let Some(channel) = globals.log_channel else {
-        return Ok(());
-    };
+const SYSTEM_COLOUR: u32 = 0xe74c3c;
+const EMAIL_COLOUR: u32 = 0xe67e22;
+const BROADCAST_COLOUR: u32 = 0xf1c40f;
+
+fn chat_colour(kind: &str) -> u32 {
+    match kind {
+        "FreeChat" => 0x3498db,
+        "BuddyChat" => 0x2ecc71,
+        "GroupChat" => 0x9b59b6,
+        _ => 0x95a5a6,
+    }
+}
 
-    let mut message = match chat.to {
+fn format_chat_event(chat: &ChatEvent) -> String {
+    let mut message = match &chat.to {
         Some(to) => format!(
             "[{}] {} (to {}): {}",
             chat.kind, chat.from, to, chat.message
@@ -22,79 +61,667 @@ async fn handle_chat_event(globals: &Globals, chat: ChatEvent) -> Result<()> {
     if chat.from == "SYS" {
         message = format!("**{}**", message);
     }
-
-    send_message(channel, &message).await?;
-    Ok(())
+    message
 }
 
-async fn handle_bcast_event(globals: &Globals, bcast: BroadcastEvent) -> Result<()> {
-    let Some(channel) = globals.log_channel else {
-        return Ok(());
+fn embed_chat_event(chat: &ChatEvent) -> Embed {
+    let colour = if chat.from == "SYS" {
+        SYSTEM_COLOUR
+    } else {
+        chat_colour(&chat.kind)
     };
+    let mut embed = Embed {
+        title: Some(chat.kind.clone()),
+        description: Some(chat.message.clone()),
+        colour,
+        ..Default::default()
+    }
+    .field("From", &chat.from, true);
+    if let Some(to) = &chat.to {
+        embed = embed.field("To", to, true);
+    }
+    embed
+}
 
-    let message = format!(
-        "**[Broadcast] ({:?}) {}: {}**",
+fn format_bcast_event(bcast: &BroadcastEvent) -> String {
+    format!(
+        "**[Broadcast] ({}) {}: {}**",
         bcast.scope, bcast.from, bcast.message,
-    );
-    send_message(channel, &message).await?;
-    Ok(())
+    )
 }
 
-async fn handle_email_event(globals: &Globals, email: EmailEvent) -> Result<()> {
-    let Some(channel) = globals.log_channel else {
-        return Ok(());
-    };
+fn embed_bcast_event(bcast: &BroadcastEvent) -> Embed {
+    Embed {
+        title: Some(format!("Broadcast ({})", bcast.scope)),
+        description: Some(bcast.message.clone()),
+        colour: BROADCAST_COLOUR,
+        ..Default::default()
+    }
+    .field("From", &bcast.from, true)
+}
 
-    let subject = email.subject.unwrap_or("no subject".to_string());
+fn format_email_event(email: &EmailEvent) -> String {
+    let subject = email.subject.as_deref().unwrap_or("no subject");
     let body = email.body.join("\n");
-    let message = format!(
+    format!(
         "[Email] {} (to {}): <{}>\n```{}```",
         email.from, email.to, subject, body
+    )
+}
+
+fn embed_email_event(email: &EmailEvent) -> Embed {
+    Embed {
+        title: Some("Email".to_string()),
+        description: Some(format!("```{}```", email.body.join("\n"))),
+        colour: EMAIL_COLOUR,
+        ..Default::default()
+    }
+    .field("From", &email.from, true)
+    .field("To", &email.to, true)
+    .field(
+        "Subject",
+        email.subject.as_deref().unwrap_or("no subject"),
+        false,
+    )
+}
+
+/// Relays an event to the log channel in whichever format is set for that channel.
+fn log_event<T>(
+    settings: &Settings,
+    event: &T,
+    format_of: fn(&ChannelFormats) -> MessageFormat,
+    text: fn(&T) -> String,
+    embed: fn(&T) -> Embed,
+) -> Option<Output> {
+    let channel = settings.log_channel?;
+    let output = match format_of(&settings.formats_for(channel)) {
+        MessageFormat::Text => Output::Message {
+            channel,
+            content: text(event),
+        },
+        MessageFormat::Embed => Output::Embed {
+            channel,
+            embed: embed(event),
+        },
+    };
+    Some(output)
+}
+
+fn process_event(settings: &Settings, event: Event) -> Option<Output> {
+    match event {
+        Event::Chat(chat) => log_event(
+            settings,
+            &chat,
+            |f| f.chat,
+            format_chat_event,
+            embed_chat_event,
+        ),
+        Event::Email(email) => log_event(
+            settings,
+            &email,
+            |f| f.email,
+            format_email_event,
+            embed_email_event,
+        ),
+        Event::Broadcast(bcast) => log_event(
+            settings,
+            &bcast,
+            |f| f.broadcast,
+            format_bcast_event,
+            embed_bcast_event,
+        ),
+        Event::NameRequest(name_request) => {
+            settings
+                .name_approvals_channel
+                .map(|channel| Output::NameRequest {
+                    channel,
+                    name_request,
+                    context: vec![],
+                })
+        }
+    }
+}
+
+fn process_update(settings: &Settings, player_count: usize, events: Vec<Event>) -> Vec<Output> {
+    let mut outputs = vec![Output::Status(Some(player_count))];
+    for event in events {
+        outputs.extend(process_event(settings, event));
+    }
+    outputs
+}
+
+/// Works out what the bot should do about a notification, without doing any of it.
+pub(crate) fn process_notification(settings: &Settings, notification: Notification) -> Vec<Output> {
+    match notification {
+        Notification::Connected => vec![],
+        Notification::Disconnected => vec![Output::Status(None)],
+        Notification::Updated {
+            player_count,
+            events,
+        } => process_update(settings, player_count, events),
+    }
+}
+
+/// Carries out an output, returning the message it posted, if any.
+/// Embeds are stamped with `at`, when the event came in.
+pub(crate) async fn deliver(
+    sink: &dyn Sink,
+    output: Output,
+    at: SystemTime,
+) -> Result<Option<MessageRef>> {
+    let message = match output {
+        Output::Status(num_players) => {
+            sink.set_presence(&format_status(num_players)).await?;
+            None
+        }
+        Output::Message { channel, content } => Some(sink.send(channel, &content).await?),
+        Output::Embed { channel, mut embed } => {
+            // the monitor doesn't say when events happened in game, so this is as close as we get
+            embed.timestamp.get_or_insert(at);
+            Some(sink.send_embed(channel, embed).await?)
+        }
+        Output::NameRequest {
+            channel,
+            name_request,
+            context,
+        } => Some(util::send_name_request_message(sink, channel, &name_request, &context).await?),
+    };
+    Ok(message)
+}
+
+/// What relaying a name request needs, so dry runs can do it without Discord.
+pub(crate) struct Relay<'a> {
+    pub(crate) sink: &'a dyn Sink,
+    pub(crate) ofapi: &'a OfapiClient,
+    /// For context about the player; dry runs don't have one
+    pub(crate) archive: Option<&'a Archive>,
+    pub(crate) settings: &'a Settings,
+}
+
+/// The lines to post under a name request, and the names it imitates.
+fn describe_name_request(
+    archive: Option<&Archive>,
+    settings: &Settings,
+    name_request: &NameRequest,
+) -> (Vec<String>, Vec<impersonation::Match>) {
+    let found = archive.map(|archive| archive.name_request_context(name_request));
+    let similar_names = found.as_ref().map_or(&[][..], |found| &found.similar_names);
+    let matches = impersonation::check(
+        &name_request.requested_name,
+        similar_names,
+        &settings.impersonation,
     );
-    send_message(channel, &message).await?;
-    Ok(())
+    let mut context = Vec::new();
+    if !matches.is_empty() {
+        context.push(impersonation::format_warning(&matches));
+    }
+    context.extend(found.iter().flat_map(|found| found.lines()));
+    (context, matches)
 }
 
-async fn handle_name_request_event(
+/// Posts a name request along with what's known about the player, or denies it
+/// straight away if it imitates staff or a protected name and the config says to.
+/// Requests from a replayed recording (`replaying`) are never denied, since
+/// that would reach the real OFAPI.
+pub(crate) async fn relay_name_request(
+    relay: &Relay<'_>,
+    channel: ChannelId,
+    name_request: NameRequest,
+    replaying: bool,
+) -> Result<Option<MessageRef>> {
+    let settings = relay.settings;
+    let (mut context, matches) = describe_name_request(relay.archive, settings, &name_request);
+
+    if !replaying && impersonation::should_auto_deny(&matches, &settings.impersonation) {
+        let by = "impersonation check";
+        match namereq::decide_request(relay.ofapi, name_request.clone(), Decision::Denied, by).await
+        {
+            Outcome::Decided(namereq) => {
+                println!(
+                    "Denied name request from Player {} automatically: {}",
+                    namereq.player_uid, namereq.requested_name
+                );
+                if let Some(archive) = relay.archive {
+                    if let Err(e) = archive.record_decision(&namereq, Decision::Denied) {
+                        println!("Error while archiving name request decision: {}", e);
+                    }
+                }
+                if let Some(log_channel) = settings.log_channel {
+                    let by = format!("the {}", by);
+                    let content = namereq::format_log_message(&namereq, Decision::Denied, by);
+                    relay.sink.send(log_channel, &content).await?;
+                }
+                let content = impersonation::format_auto_denied(&namereq, &matches);
+                return Ok(Some(relay.sink.send(channel, &content).await?));
+            }
+            Outcome::AlreadyProcessed => return Ok(None),
+            // leave it to the moderators
+            Outcome::Failed(e) => context.push(format!("- {}", e)),
+        }
+    }
+
+    let message = util::send_name_request_message(relay.sink, channel, &name_request, &context);
+    Ok(Some(message.await?))
+}
+
+/// Relays a notification to Discord. `at` is when it came in, or when it was
+/// recorded for a replay. Events from a replayed recording (`replaying`) have
+/// already happened, so they're relayed without being archived again.
+pub(crate) async fn handle_notification(
     globals: &Globals,
-    name_request_event: NameRequestEvent,
+    notification: Notification,
+    at: SystemTime,
+    replaying: bool,
 ) -> Result<()> {
-    let Some(channel) = globals.name_approvals_channel else {
-        return Ok(());
+    match notification {
+        Notification::Connected => println!("Connected to monitor"),
+        Notification::Disconnected => println!("Disconnected from monitor"),
+        _ => {}
+    }
+    if !replaying {
+        globals
+            .state
+            .lock()
+            .await
+            .digest
+            .record_notification(&notification, at);
+    }
+    let settings = globals.settings();
+    let relay = Relay {
+        sink: globals.sink.as_ref(),
+        ofapi: &globals.ofapi,
+        archive: Some(&*globals.archive),
+        settings: &settings,
+    };
+    // events are handled one by one so each can be archived with where it was relayed
+    let (outputs, events) = match notification {
+        Notification::Updated {
+            player_count,
+            events,
+        } => (vec![Output::Status(Some(player_count))], events),
+        notification => (process_notification(&settings, notification), vec![]),
     };
-    let name_request: NameRequest = name_request_event.into();
-    util::send_name_request_message(channel, &name_request).await?;
+    for output in outputs {
+        if let Output::Status(num_players) = output {
+            globals.state.lock().await.last_player_count = num_players;
+        }
+        deliver(globals.sink.as_ref(), output, at).await?;
+    }
+    for event in events {
+        let relayed = match process_event(&settings, event.clone()) {
+            Some(Output::NameRequest {
+                channel,
+                name_request,
+                ..
+            }) => relay_name_request(&relay, channel, name_request, replaying).await,
+            Some(output) => deliver(globals.sink.as_ref(), output, at).await,
+            None => Ok(None),
+        };
+        if !replaying {
+            let message = relayed.as_ref().ok().copied().flatten();
+            if let Err(e) = globals.archive.record(event, message, at) {
+                println!("Error while archiving event: {}", e);
+            }
+        }
+        relayed?;
+    }
     Ok(())
 }
 
-async fn handle_update(globals: &Globals, update: MonitorUpdate) -> Result<()> {
-    let num_players = update.get_player_count();
-    update_status(Some(num_players)).await?;
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, UNIX_EPOCH},
+    };
 
-    let events = update.get_events();
-    for event in events {
-        match event {
-            Event::Chat(chat_event) => handle_chat_event(globals, chat_event).await?,
-            Event::Email(email_event) => handle_email_event(globals, email_event).await?,
-            Event::Broadcast(bcast_event) => handle_bcast_event(globals, bcast_event).await?,
-            Event::NameRequest(name_request_event) => {
-                handle_name_request_event(globals, name_request_event).await?
+    use ffmonitor::{Monitor, MonitorNotification};
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::{
+        dry_run::DryRun,
+        testing::{self, FakeMonitor, FakeUpdate, MemorySink, MockOfapi, Sent},
+    };
+
+    struct Harness {
+        server: FakeMonitor,
+        notifications: mpsc::UnboundedReceiver<MonitorNotification>,
+        _monitor: Monitor,
+    }
+    impl Harness {
+        async fn start() -> Self {
+            let server = FakeMonitor::start().await;
+            let (tx, notifications) = mpsc::unbounded_channel();
+            let callback = move |notification| {
+                let _ = tx.send(notification);
+            };
+            let monitor = Monitor::new_with_callback(&server.address(), Box::new(callback))
+                .expect("couldn't start monitor");
+            let mut harness = Self {
+                server,
+                notifications,
+                _monitor: monitor,
+            };
+            let connected = harness.next().await;
+            assert_eq!(connected, Notification::Connected);
+            harness
+        }
+
+        async fn next(&mut self) -> Notification {
+            tokio::time::timeout(Duration::from_secs(5), self.notifications.recv())
+                .await
+                .expect("timed out waiting for a notification")
+                .expect("monitor went away")
+                .into()
+        }
+
+        /// Sends an update and returns what the bot would do about it.
+        async fn process(&mut self, update: FakeUpdate) -> Vec<Output> {
+            self.server.send(update);
+            let notification = self.next().await;
+            process_notification(&testing::settings(), notification)
+        }
+    }
+
+    fn log(content: &str) -> Output {
+        Output::Message {
+            channel: ChannelId::new(testing::LOG_CHANNEL),
+            content: content.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn player_count() {
+        let mut harness = Harness::start().await;
+        let outputs = harness
+            .process(FakeUpdate::new().player("Alice").player("Bob"))
+            .await;
+        assert_eq!(outputs, vec![Output::Status(Some(2))]);
+    }
+
+    #[tokio::test]
+    async fn chat() {
+        let mut harness = Harness::start().await;
+        let update = FakeUpdate::new()
+            .player("Alice")
+            .chat("FreeChat", "Alice", None, "hello")
+            .chat("BuddyChat", "Alice", Some("Bob"), "psst")
+            .chat("FreeChat", "SYS", None, "Server restarting");
+        let outputs = harness.process(update).await;
+        assert_eq!(
+            outputs,
+            vec![
+                Output::Status(Some(1)),
+                log("[FreeChat] Alice: hello"),
+                log("[BuddyChat] Alice (to Bob): psst"),
+                log("**[FreeChat] SYS: Server restarting**"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn email() {
+        let mut harness = Harness::start().await;
+        let update = FakeUpdate::new().email("Alice", "Bob", Some("Hi"), &["line 1", "line 2"]);
+        let outputs = harness.process(update).await;
+        assert_eq!(
+            outputs[1..],
+            [log("[Email] Alice (to Bob): <Hi>\n```line 1\nline 2```")]
+        );
+    }
+
+    #[tokio::test]
+    async fn broadcast() {
+        let mut harness = Harness::start().await;
+        let update = FakeUpdate::new().broadcast("Shard", "GM", "Event starting");
+        let outputs = harness.process(update).await;
+        assert_eq!(
+            outputs[1..],
+            [log("**[Broadcast] (Shard) GM: Event starting**")]
+        );
+    }
+
+    #[tokio::test]
+    async fn name_request() {
+        let mut harness = Harness::start().await;
+        let update = FakeUpdate::new().name_request(42, "Bob");
+        let outputs = harness.process(update).await;
+        assert_eq!(
+            outputs[1..],
+            [Output::NameRequest {
+                channel: ChannelId::new(testing::NAME_APPROVALS_CHANNEL),
+                name_request: NameRequest {
+                    player_uid: 42,
+                    requested_name: "Bob".to_string(),
+                },
+                context: vec![],
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn embeds() {
+        let mut harness = Harness::start().await;
+        harness.server.send(
+            FakeUpdate::new()
+                .chat("BuddyChat", "Alice", Some("Bob"), "psst")
+                .email("Alice", "Bob", None, &["hi"])
+                .broadcast("Shard", "GM", "Event starting"),
+        );
+        let notification = harness.next().await;
+        let settings = testing::settings_with(serde_json::json!({
+            "message_formats": {
+                testing::LOG_CHANNEL.to_string(): { "email": "embed", "broadcast": "embed" }
             }
-            _ => {}
+        }));
+        let outputs = process_notification(&settings, notification);
+        assert_eq!(outputs[1], log("[BuddyChat] Alice (to Bob): psst"));
+
+        let channel = ChannelId::new(testing::LOG_CHANNEL);
+        let email = Embed {
+            title: Some("Email".to_string()),
+            description: Some("```hi```".to_string()),
+            colour: EMAIL_COLOUR,
+            ..Default::default()
         }
+        .field("From", "Alice", true)
+        .field("To", "Bob", true)
+        .field("Subject", "no subject", false);
+        assert_eq!(
+            outputs[2],
+            Output::Embed {
+                channel,
+                embed: email
+            }
+        );
+        let Output::Embed { embed, .. } = &outputs[3] else {
+            panic!("expected embed, got {:?}", outputs[3]);
+        };
+        assert_eq!(embed.title.as_deref(), Some("Broadcast (Shard)"));
+        assert_eq!(embed.colour, BROADCAST_COLOUR);
+
+        // other channels keep the default
+        let settings = testing::settings_with(serde_json::json!({
+            "message_formats": { testing::MOD_CHANNEL.to_string(): "embed" }
+        }));
+        assert!(matches!(
+            process_event(
+                &settings,
+                Event::Email(EmailEvent {
+                    from: "A".to_string(),
+                    to: "B".to_string(),
+                    subject: None,
+                    body: vec![],
+                })
+            ),
+            Some(Output::Message { .. })
+        ));
     }
-    Ok(())
-}
 
-pub(crate) async fn handle_notification(event: MonitorNotification) -> Result<()> {
-    let globals = GLOBALS.get().unwrap();
-    match event {
-        MonitorNotification::Connected => println!("Connected to monitor"),
-        MonitorNotification::Disconnected => {
-            println!("Disconnected from monitor");
-            update_status(None).await?;
+    #[tokio::test]
+    async fn outputs_are_delivered_to_sink() {
+        let sink = MemorySink::default();
+        let name_request = NameRequest {
+            player_uid: 42,
+            requested_name: "Bob".to_string(),
+        };
+        let outputs = vec![
+            Output::Status(Some(1)),
+            log("[FreeChat] Alice: hello"),
+            Output::NameRequest {
+                channel: ChannelId::new(testing::NAME_APPROVALS_CHANNEL),
+                name_request: name_request.clone(),
+                context: vec!["- Earlier requests: **1**".to_string()],
+            },
+            Output::Status(None),
+        ];
+        for output in outputs {
+            deliver(&sink, output, SystemTime::now()).await.unwrap();
         }
-        MonitorNotification::Updated(update) => handle_update(globals, update).await?,
+
+        let sent = sink.sent();
+        assert_eq!(sent.len(), 4);
+        assert_eq!(sent[0], Sent::Presence("1 player".to_string()));
+        let Sent::Message {
+            message, content, ..
+        } = &sent[1]
+        else {
+            panic!("expected message, got {:?}", sent[1]);
+        };
+        assert_eq!(message.channel, ChannelId::new(testing::LOG_CHANNEL));
+        assert_eq!(content, "[FreeChat] Alice: hello");
+        let Sent::Message {
+            message,
+            content,
+            buttons,
+        } = &sent[2]
+        else {
+            panic!("expected message, got {:?}", sent[2]);
+        };
+        assert_eq!(
+            message.channel,
+            ChannelId::new(testing::NAME_APPROVALS_CHANNEL)
+        );
+        assert_eq!(
+            content,
+            "Name request from Player 42: **Bob**\n- Earlier requests: **1**"
+        );
+        // the buttons still find the request behind the context
+        let parsed = NameRequest::parse_from_notification_message(content).unwrap();
+        assert_eq!(parsed, name_request);
+        let ids: Vec<&str> = buttons.iter().map(|b| b.id.as_str()).collect();
+        assert_eq!(ids, ["namereq_approve", "namereq_deny"]);
+        assert_eq!(sent[3], Sent::Presence("nothing".to_string()));
+    }
+
+    #[tokio::test]
+    async fn embeds_are_stamped_with_event_time() {
+        let sink = MemorySink::default();
+        let channel = ChannelId::new(testing::LOG_CHANNEL);
+        // e.g. when a replayed notification was recorded
+        let at = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let embed = Embed::default();
+        deliver(&sink, Output::Embed { channel, embed }, at)
+            .await
+            .unwrap();
+
+        let Sent::Embed { embed, .. } = &sink.sent()[0] else {
+            panic!("expected embed, got {:?}", sink.sent()[0]);
+        };
+        assert_eq!(embed.timestamp, Some(at));
+    }
+
+    #[tokio::test]
+    async fn impersonation_in_dry_run() {
+        let mock = MockOfapi::start().await;
+        let path = testing::temp_path("dry-run");
+        let settings = testing::settings_with(serde_json::json!({
+            "impersonation": { "protected_names": ["Admin"], "auto_deny": true }
+        }));
+        let dry_run = Arc::new(DryRun::new(path.to_str(), settings.clone()).unwrap());
+        let ofapi = mock.client().with_dry_run(dry_run);
+        let sink = MemorySink::default();
+        let relay = Relay {
+            sink: &sink,
+            ofapi: &ofapi,
+            archive: None,
+            settings: &settings,
+        };
+        let channel = ChannelId::new(testing::NAME_APPROVALS_CHANNEL);
+        let request = |name: &str| NameRequest {
+            player_uid: 42,
+            requested_name: name.to_string(),
+        };
+
+        relay_name_request(&relay, channel, request("4dm1n"), false)
+            .await
+            .unwrap();
+        relay_name_request(&relay, channel, request("Badminton"), false)
+            .await
+            .unwrap();
+        // replays leave it to the moderators
+        relay_name_request(&relay, channel, request("Admin"), true)
+            .await
+            .unwrap();
+
+        let sent: Vec<(u64, String)> = sink
+            .sent()
+            .into_iter()
+            .map(|sent| match sent {
+                Sent::Message {
+                    message, content, ..
+                } => (message.channel.get(), content),
+                sent => panic!("expected message, got {:?}", sent),
+            })
+            .collect();
+        let approvals = testing::NAME_APPROVALS_CHANNEL;
+        assert_eq!(
+            sent,
+            [
+                (
+                    testing::LOG_CHANNEL,
+                    "Name request from Player 42 **denied** :no_entry: by the impersonation check: 4dm1n".to_string()
+                ),
+                (
+                    approvals,
+                    "Name request from Player 42: **4dm1n**\n\
+                     Denied automatically :no_entry: (looks like protected name **Admin**)"
+                        .to_string()
+                ),
+                (
+                    approvals,
+                    "Name request from Player 42: **Badminton**\n\
+                     :warning: **Possible impersonation:** looks like protected name **Admin** (in part)"
+                        .to_string()
+                ),
+                (
+                    approvals,
+                    "Name request from Player 42: **Admin**\n\
+                     :warning: **Possible impersonation:** looks like protected name **Admin**"
+                        .to_string()
+                ),
+            ]
+        );
+
+        // the denial never reached OFAPI
+        assert!(mock.requests().is_empty());
+        let output = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(output.lines().count(), 1);
+        assert!(output.starts_with(&format!("OFAPI: POST {}/namereq ", mock.base_url())));
+        assert!(output.contains(r#""decision":"denied""#));
+    }
+
+    #[tokio::test]
+    async fn disconnect() {
+        let mut harness = Harness::start().await;
+        harness.server.disconnect();
+        let notification = harness.next().await;
+        assert_eq!(notification, Notification::Disconnected);
+        assert_eq!(
+            process_notification(&testing::settings(), notification),
+            vec![Output::Status(None)]
+        );
     }
-    Ok(())
 }
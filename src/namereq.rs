@@ -0,0 +1,497 @@
+use std::{fmt::Display, time::SystemTime};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    endpoint::{self, OfapiClient},
+    util, NameRequest, Result,
+};
+
+/// Most requests listed by name in a bulk summary or audit entry
+const MAX_LISTED: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, poise::ChoiceParameter)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Decision {
+    #[name = "Approve"]
+    Approved,
+    #[name = "Deny"]
+    Denied,
+}
+impl Decision {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Decision::Approved => "approved",
+            Decision::Denied => "denied",
+        }
+    }
+
+    /// The permissions action for making this decision
+    pub(crate) fn action(&self) -> &'static str {
+        match self {
+            Decision::Approved => "namereq_approve",
+            Decision::Denied => "namereq_deny",
+        }
+    }
+
+    fn verb(&self) -> &'static str {
+        match self {
+            Decision::Approved => "approve",
+            Decision::Denied => "deny",
+        }
+    }
+
+    fn emoji(&self) -> &'static str {
+        match self {
+            Decision::Approved => ":white_check_mark:",
+            Decision::Denied => ":no_entry:",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum Outcome {
+    /// The decision went through
+    Decided(NameRequest),
+    /// Someone else got to the request first
+    AlreadyProcessed,
+    /// OFAPI couldn't take the decision; holds a message for the moderator
+    Failed(String),
+}
+
+/// Sends a moderator's decision on the name request in `message` (the notification we posted) to OFAPI.
+pub(crate) async fn decide(
+    ofapi: &OfapiClient,
+    message: &str,
+    decision: Decision,
+    by: &str,
+) -> Result<Outcome> {
+    let namereq = NameRequest::parse_from_notification_message(message)?;
+    Ok(decide_request(ofapi, namereq, decision, by).await)
+}
+
+/// Sends a moderator's decision on `namereq` to OFAPI.
+pub(crate) async fn decide_request(
+    ofapi: &OfapiClient,
+    namereq: NameRequest,
+    decision: Decision,
+    by: &str,
+) -> Outcome {
+    match endpoint::send_name_request_decision(ofapi, &namereq, decision.as_str(), by).await {
+        Ok(true) => Outcome::Decided(namereq),
+        Ok(false) => Outcome::AlreadyProcessed,
+        Err(e) => {
+            println!("Failed to send name request decision: {}", e);
+            Outcome::Failed(format!(
+                "Couldn't {} the name request. {}",
+                decision.verb(),
+                e.user_message()
+            ))
+        }
+    }
+}
+
+pub(crate) fn format_log_message(
+    namereq: &NameRequest,
+    decision: Decision,
+    by: impl Display,
+) -> String {
+    format!(
+        "Name request from Player {} **{}** {} by {}: {}",
+        namereq.player_uid,
+        decision.as_str(),
+        decision.emoji(),
+        by,
+        namereq.requested_name
+    )
+}
+
+/// Narrows down the outstanding name requests.
+#[derive(Debug, Default)]
+pub(crate) struct Filter {
+    pub(crate) name: Option<Regex>,
+    pub(crate) min_uid: Option<u64>,
+    pub(crate) max_uid: Option<u64>,
+    /// Only requests the bot saw come in before this
+    pub(crate) seen_before: Option<SystemTime>,
+}
+impl Filter {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.name.is_none()
+            && self.min_uid.is_none()
+            && self.max_uid.is_none()
+            && self.seen_before.is_none()
+    }
+
+    /// `seen` is when the bot saw the request come in, if it knows.
+    pub(crate) fn matches(&self, namereq: &NameRequest, seen: Option<SystemTime>) -> bool {
+        self.name
+            .as_ref()
+            .is_none_or(|name| name.is_match(&namereq.requested_name))
+            && self.min_uid.is_none_or(|min| namereq.player_uid >= min)
+            && self.max_uid.is_none_or(|max| namereq.player_uid <= max)
+            // requests of unknown age never count as old enough
+            && self
+                .seen_before
+                .is_none_or(|before| seen.is_some_and(|seen| seen < before))
+    }
+}
+impl Display for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(name) = &self.name {
+            parts.push(format!("name matching `{}`", name));
+        }
+        match (self.min_uid, self.max_uid) {
+            (Some(min), Some(max)) => parts.push(format!("UID {} to {}", min, max)),
+            (Some(min), None) => parts.push(format!("UID {} and up", min)),
+            (None, Some(max)) => parts.push(format!("UID up to {}", max)),
+            (None, None) => {}
+        }
+        if let Some(before) = self.seen_before {
+            parts.push(format!(
+                "seen before {}",
+                util::discord_timestamp(before, util::TimestampStyle::ShortDateTime)
+            ));
+        }
+        if parts.is_empty() {
+            write!(f, "all requests")
+        } else {
+            write!(f, "{}", parts.join(", "))
+        }
+    }
+}
+
+fn list_requests(reqs: &[NameRequest]) -> Vec<String> {
+    let mut lines: Vec<String> = reqs
+        .iter()
+        .take(MAX_LISTED)
+        .map(|req| format!("- Player {}: **{}**", req.player_uid, req.requested_name))
+        .collect();
+    if reqs.len() > MAX_LISTED {
+        lines.push(format!("- and {} more", reqs.len() - MAX_LISTED));
+    }
+    lines
+}
+
+/// What a bulk decision is about to do, for the moderator to confirm.
+pub(crate) fn format_bulk_summary(
+    reqs: &[NameRequest],
+    decision: Decision,
+    filter: &Filter,
+) -> String {
+    let mut lines = vec![format!(
+        "This will **{}** {} name request(s) ({}):",
+        decision.verb(),
+        reqs.len(),
+        filter
+    )];
+    lines.extend(list_requests(reqs));
+    lines.join("\n")
+}
+
+/// How a bulk decision went.
+#[derive(Debug, Default)]
+pub(crate) struct BulkResult {
+    pub(crate) decided: Vec<NameRequest>,
+    pub(crate) already_processed: usize,
+    pub(crate) failed: usize,
+}
+impl BulkResult {
+    pub(crate) fn record(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Decided(namereq) => self.decided.push(namereq),
+            Outcome::AlreadyProcessed => self.already_processed += 1,
+            Outcome::Failed(_) => self.failed += 1,
+        }
+    }
+
+    pub(crate) fn num_done(&self) -> usize {
+        self.decided.len() + self.already_processed + self.failed
+    }
+
+    fn format_counts(&self, decision: Decision) -> String {
+        format!(
+            "**{}** {}, {} already processed, {} failed",
+            self.decided.len(),
+            decision.as_str(),
+            self.already_processed,
+            self.failed
+        )
+    }
+
+    /// The single audit entry for the log channel.
+    pub(crate) fn format_log_message(
+        &self,
+        decision: Decision,
+        filter: &Filter,
+        by: impl Display,
+    ) -> String {
+        let mut lines = vec![format!(
+            "Bulk decision {} by {} ({}): {}",
+            decision.emoji(),
+            by,
+            filter,
+            self.format_counts(decision)
+        )];
+        lines.extend(list_requests(&self.decided));
+        lines.join("\n")
+    }
+
+    pub(crate) fn format_progress(&self, decision: Decision, total: usize) -> String {
+        format!(
+            "Processed {}/{}: {}",
+            self.num_done(),
+            total,
+            self.format_counts(decision)
+        )
+    }
+}
+
+/// How many requests fit on a page of `/namereqs`, each with a row of buttons
+pub(crate) const PAGE_SIZE: usize = 4;
+
+pub(crate) fn num_pages(num_requests: usize) -> usize {
+    num_requests.div_ceil(PAGE_SIZE).max(1)
+}
+
+/// One page of the `/namereqs` view, with `note` (the result of the last action) at the bottom.
+pub(crate) fn format_page(
+    reqs: &[NameRequest],
+    filter: &Filter,
+    page: usize,
+    note: Option<&str>,
+) -> String {
+    let mut header = match reqs.len() {
+        0 => "No outstanding name requests".to_string(),
+        1 => "**1** outstanding name request".to_string(),
+        n => format!("**{}** outstanding name requests", n),
+    };
+    if !filter.is_empty() {
+        header.push_str(&format!(" ({})", filter));
+    }
+    let mut lines = vec![header];
+    let start = page * PAGE_SIZE;
+    for (i, req) in reqs.iter().enumerate().skip(start).take(PAGE_SIZE) {
+        lines.push(format!(
+            "`{}.` Player {}: **{}**",
+            i + 1,
+            req.player_uid,
+            req.requested_name
+        ));
+    }
+    let num_pages = num_pages(reqs.len());
+    if num_pages > 1 {
+        lines.push(format!("-# Page {}/{}", page + 1, num_pages));
+    }
+    if let Some(note) = note {
+        lines.push(format!("> {}", note));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockOfapi;
+
+    const MESSAGE: &str = "Name request from Player 42: **Bob**";
+
+    fn namereqs(count: u64) -> Vec<NameRequest> {
+        (1..=count)
+            .map(|player_uid| NameRequest {
+                player_uid,
+                requested_name: format!("Name{}", player_uid),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pages() {
+        assert_eq!(num_pages(0), 1);
+        assert_eq!(num_pages(4), 1);
+        assert_eq!(num_pages(5), 2);
+
+        let filter = Filter::default();
+        assert_eq!(
+            format_page(&[], &filter, 0, None),
+            "No outstanding name requests"
+        );
+        let reqs = namereqs(6);
+        assert_eq!(
+            format_page(&reqs, &filter, 1, Some("Approved **Name1**")),
+            "**6** outstanding name requests\n\
+             `5.` Player 5: **Name5**\n\
+             `6.` Player 6: **Name6**\n\
+             -# Page 2/2\n\
+             > Approved **Name1**"
+        );
+
+        let filter = Filter {
+            min_uid: Some(6),
+            ..Default::default()
+        };
+        assert_eq!(
+            format_page(&reqs[5..], &filter, 0, None),
+            "**1** outstanding name request (UID 6 and up)\n`1.` Player 6: **Name6**"
+        );
+    }
+
+    #[test]
+    fn filters() {
+        let seen = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(100);
+        let filter = Filter {
+            name: Some(Regex::new("^Name[12]$").unwrap()),
+            min_uid: Some(2),
+            ..Default::default()
+        };
+        let matching: Vec<u64> = namereqs(5)
+            .iter()
+            .filter(|req| filter.matches(req, None))
+            .map(|req| req.player_uid)
+            .collect();
+        assert_eq!(matching, [2]);
+        assert_eq!(
+            filter.to_string(),
+            "name matching `^Name[12]$`, UID 2 and up"
+        );
+
+        let filter = Filter {
+            seen_before: Some(seen),
+            ..Default::default()
+        };
+        let req = &namereqs(1)[0];
+        assert!(filter.matches(req, Some(seen - std::time::Duration::from_secs(1))));
+        assert!(!filter.matches(req, Some(seen)));
+        assert!(!filter.matches(req, None));
+        assert!(Filter::default().matches(req, None));
+        assert!(Filter::default().is_empty());
+    }
+
+    #[tokio::test]
+    async fn bulk() {
+        let mock = MockOfapi::start().await;
+        mock.respond("POST", "/namereq", 200, "");
+        mock.respond("POST", "/namereq", 208, "");
+        mock.respond("POST", "/namereq", 200, "");
+
+        let filter = Filter {
+            max_uid: Some(12),
+            ..Default::default()
+        };
+        let reqs = namereqs(12);
+        assert_eq!(
+            format_bulk_summary(&reqs[..2], Decision::Denied, &filter),
+            "This will **deny** 2 name request(s) (UID up to 12):\n\
+             - Player 1: **Name1**\n\
+             - Player 2: **Name2**"
+        );
+        assert!(format_bulk_summary(&reqs, Decision::Denied, &filter).ends_with("- and 2 more"));
+
+        let mut result = BulkResult::default();
+        for req in &reqs[..3] {
+            let outcome =
+                decide_request(&mock.client(), req.clone(), Decision::Denied, "mod#0001").await;
+            result.record(outcome);
+        }
+        assert_eq!(mock.requests().len(), 3);
+        assert_eq!(
+            result.format_progress(Decision::Denied, 5),
+            "Processed 3/5: **2** denied, 1 already processed, 0 failed"
+        );
+        assert_eq!(
+            result.format_log_message(Decision::Denied, &filter, "<@1>"),
+            "Bulk decision :no_entry: by <@1> (UID up to 12): **2** denied, 1 already processed, 0 failed\n\
+             - Player 1: **Name1**\n\
+             - Player 3: **Name3**"
+        );
+    }
+
+    #[tokio::test]
+    async fn approve() {
+        let mock = MockOfapi::start().await;
+        mock.respond("POST", "/namereq", 200, "");
+
+        let outcome = decide(&mock.client(), MESSAGE, Decision::Approved, "mod#0001")
+            .await
+            .unwrap();
+        let Outcome::Decided(namereq) = outcome else {
+            panic!("expected decision, got {:?}", outcome);
+        };
+        assert_eq!(namereq.player_uid, 42);
+        assert_eq!(namereq.requested_name, "Bob");
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].header("authorization"),
+            Some(format!("Bearer {}", MockOfapi::TOKEN).as_str())
+        );
+        let body = requests[0].json();
+        assert_eq!(body["player_uid"], 42);
+        assert_eq!(body["requested_name"], "Bob");
+        assert_eq!(body["decision"], "approved");
+        assert_eq!(body["by"], "mod#0001");
+
+        assert_eq!(
+            format_log_message(&namereq, Decision::Approved, "<@1>"),
+            "Name request from Player 42 **approved** :white_check_mark: by <@1>: Bob"
+        );
+    }
+
+    #[tokio::test]
+    async fn deny() {
+        let mock = MockOfapi::start().await;
+        mock.respond("POST", "/namereq", 200, "");
+
+        let outcome = decide(&mock.client(), MESSAGE, Decision::Denied, "mod#0001")
+            .await
+            .unwrap();
+        let Outcome::Decided(namereq) = outcome else {
+            panic!("expected decision, got {:?}", outcome);
+        };
+        assert_eq!(mock.requests()[0].json()["decision"], "denied");
+        assert_eq!(
+            format_log_message(&namereq, Decision::Denied, "<@1>"),
+            "Name request from Player 42 **denied** :no_entry: by <@1>: Bob"
+        );
+    }
+
+    #[tokio::test]
+    async fn already_processed() {
+        let mock = MockOfapi::start().await;
+        mock.respond("POST", "/namereq", 208, "");
+
+        let outcome = decide(&mock.client(), MESSAGE, Decision::Approved, "mod#0001")
+            .await
+            .unwrap();
+        assert!(matches!(outcome, Outcome::AlreadyProcessed));
+    }
+
+    #[tokio::test]
+    async fn error_is_reported_and_not_retried() {
+        let mock = MockOfapi::start().await;
+        mock.respond("POST", "/namereq", 409, r#"{"error": "player is online"}"#);
+
+        let outcome = decide(&mock.client(), MESSAGE, Decision::Denied, "mod#0001")
+            .await
+            .unwrap();
+        let Outcome::Failed(msg) = outcome else {
+            panic!("expected failure, got {:?}", outcome);
+        };
+        assert_eq!(
+            msg,
+            "Couldn't deny the name request. OFAPI reported a conflict: player is online"
+        );
+        assert_eq!(mock.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn malformed_message() {
+        let mock = MockOfapi::start().await;
+        let res = decide(&mock.client(), "hello", Decision::Approved, "mod#0001").await;
+        assert!(res.is_err());
+        assert!(mock.requests().is_empty());
+    }
+}
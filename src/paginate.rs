@@ -0,0 +1,132 @@
+//! Ephemeral replies that are too long for one message, with buttons to flip through them.
+
+use std::time::Duration;
+
+use poise::{
+    serenity_prelude::{
+        ButtonStyle, ComponentInteractionCollector, CreateActionRow, CreateButton,
+        CreateInteractionResponse, CreateInteractionResponseMessage,
+    },
+    CreateReply,
+};
+
+use crate::{PoiseContext, Result};
+
+/// Discord's limit on message length
+const MAX_MESSAGE_LEN: usize = 2000;
+/// How long the buttons keep working after the last press
+const TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Splits `lines` into pages of at most `per_page` lines, each starting with `header`.
+pub(crate) fn into_pages(header: &str, lines: &[String], per_page: usize) -> Vec<String> {
+    let mut pages = Vec::new();
+    let mut page = String::new();
+    let mut num_lines = 0;
+    for line in lines {
+        // leave room for the page number
+        let too_long = header.len() + page.len() + line.len() + 32 > MAX_MESSAGE_LEN;
+        if num_lines > 0 && (num_lines == per_page || too_long) {
+            pages.push(std::mem::take(&mut page));
+            num_lines = 0;
+        }
+        page.push_str(line);
+        page.push('\n');
+        num_lines += 1;
+    }
+    if !page.is_empty() || pages.is_empty() {
+        pages.push(page);
+    }
+
+    let num_pages = pages.len();
+    pages
+        .into_iter()
+        .enumerate()
+        .map(|(i, page)| {
+            let mut page = format!("{}\n{}", header, page);
+            if num_pages > 1 {
+                page.push_str(&format!("-# Page {}/{}", i + 1, num_pages));
+            }
+            page
+        })
+        .collect()
+}
+
+fn buttons(prev_id: &str, next_id: &str, page: usize, num_pages: usize) -> Vec<CreateActionRow> {
+    vec![CreateActionRow::Buttons(vec![
+        CreateButton::new(prev_id)
+            .label("Previous")
+            .style(ButtonStyle::Secondary)
+            .disabled(page == 0),
+        CreateButton::new(next_id)
+            .label("Next")
+            .style(ButtonStyle::Secondary)
+            .disabled(page + 1 == num_pages),
+    ])]
+}
+
+/// Replies with the first page and flips between pages as the buttons are pressed.
+pub(crate) async fn paginate(ctx: PoiseContext<'_>, pages: Vec<String>) -> Result<()> {
+    let prev_id = format!("{}prev", ctx.id());
+    let next_id = format!("{}next", ctx.id());
+
+    let mut reply = CreateReply::default()
+        .content(&pages[0])
+        .reply(true)
+        .ephemeral(true);
+    if pages.len() > 1 {
+        reply = reply.components(buttons(&prev_id, &next_id, 0, pages.len()));
+    }
+    let handle = ctx.send(reply).await?;
+    if pages.len() == 1 {
+        return Ok(());
+    }
+
+    let mut page = 0;
+    while let Some(press) = ComponentInteractionCollector::new(ctx)
+        .custom_ids(vec![prev_id.clone(), next_id.clone()])
+        .timeout(TIMEOUT)
+        .await
+    {
+        if press.data.custom_id == next_id {
+            page = (page + 1).min(pages.len() - 1);
+        } else if press.data.custom_id == prev_id {
+            page = page.saturating_sub(1);
+        } else {
+            continue;
+        }
+        let response = CreateInteractionResponseMessage::new()
+            .content(&pages[page])
+            .components(buttons(&prev_id, &next_id, page, pages.len()));
+        press
+            .create_response(ctx, CreateInteractionResponse::UpdateMessage(response))
+            .await?;
+    }
+
+    // the buttons won't do anything any more
+    let reply = CreateReply::default()
+        .content(&pages[page])
+        .components(vec![]);
+    let _ = handle.edit(ctx, reply).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages() {
+        let lines: Vec<String> = (1..=5).map(|n| format!("line {}", n)).collect();
+        let pages = into_pages("**Results**", &lines, 2);
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages[0], "**Results**\nline 1\nline 2\n-# Page 1/3");
+        assert_eq!(pages[2], "**Results**\nline 5\n-# Page 3/3");
+
+        let pages = into_pages("**Results**", &lines[..1], 2);
+        assert_eq!(pages, ["**Results**\nline 1\n"]);
+
+        // long lines split early to stay under Discord's limit
+        let lines = vec!["x".repeat(1500), "y".repeat(1500)];
+        assert_eq!(into_pages("", &lines, 10).len(), 2);
+    }
+}
@@ -0,0 +1,305 @@
+use std::collections::{HashMap, HashSet};
+
+use poise::serenity_prelude::{Member, RoleId, UserId};
+use serde::Deserialize;
+
+use crate::{config::Settings, Globals};
+
+/// Every slash command and button action, and whether it's restricted
+/// to moderators when the config doesn't say otherwise.
+pub(crate) const ACTIONS: [(&str, bool); 10] = [
+    ("check", false),
+    ("namereqs", true),
+    ("reload", true),
+    ("diagnose", true),
+    ("monitor", true),
+    ("search", true),
+    ("history", true),
+    ("export", true),
+    ("namereq_approve", true),
+    ("namereq_deny", true),
+];
+
+fn yes() -> bool {
+    true
+}
+
+/// Who may use a command or button action, as written in the config.
+#[derive(Debug, Deserialize)]
+pub(crate) struct PermissionRule {
+    #[serde(default)]
+    roles: Vec<u64>,
+    #[serde(default)]
+    users: Vec<u64>,
+    #[serde(default = "yes")]
+    administrator: bool,
+    #[serde(default = "yes")]
+    owner: bool,
+    #[serde(default)]
+    everyone: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Permission {
+    roles: HashSet<RoleId>,
+    users: HashSet<UserId>,
+    administrator: bool,
+    owner: bool,
+    everyone: bool,
+}
+impl Permission {
+    fn mods_only(mod_roles: &HashSet<RoleId>) -> Self {
+        Self {
+            roles: mod_roles.clone(),
+            users: HashSet::new(),
+            administrator: true,
+            owner: true,
+            everyone: false,
+        }
+    }
+
+    fn everyone() -> Self {
+        Self {
+            roles: HashSet::new(),
+            users: HashSet::new(),
+            administrator: true,
+            owner: true,
+            everyone: true,
+        }
+    }
+
+    pub(crate) fn get_roles(&self) -> impl Iterator<Item = RoleId> + '_ {
+        self.roles.iter().copied()
+    }
+
+    fn allows(&self, member: &Member, owner_id: Option<UserId>) -> bool {
+        self.everyone
+            || self.users.contains(&member.user.id)
+            || member.roles.iter().any(|r| self.roles.contains(r))
+            || (self.administrator && member.permissions.is_some_and(|p| p.administrator()))
+            || (self.owner && owner_id == Some(member.user.id))
+    }
+}
+impl From<&PermissionRule> for Permission {
+    fn from(rule: &PermissionRule) -> Self {
+        Self {
+            roles: rule.roles.iter().map(|id| RoleId::new(*id)).collect(),
+            users: rule.users.iter().map(|id| UserId::new(*id)).collect(),
+            administrator: rule.administrator,
+            owner: rule.owner,
+            everyone: rule.everyone,
+        }
+    }
+}
+
+pub(crate) fn validate_rules(rules: &HashMap<String, PermissionRule>) -> Option<String> {
+    for (action, rule) in rules {
+        if !ACTIONS.iter().any(|(a, _)| a == action) {
+            return Some(format!(
+                "Unknown command or action in permissions: {}",
+                action
+            ));
+        }
+        if rule.roles.contains(&0) || rule.users.contains(&0) {
+            return Some(format!(
+                "Invalid role or user ID in permissions for {}",
+                action
+            ));
+        }
+    }
+    None
+}
+
+/// Resolve the permission for every action, falling back to the defaults
+/// for actions that aren't mentioned in the config.
+pub(crate) fn resolve(
+    rules: &HashMap<String, PermissionRule>,
+    mod_roles: &HashSet<RoleId>,
+) -> HashMap<String, Permission> {
+    ACTIONS
+        .iter()
+        .map(|(action, privileged)| {
+            let permission = match rules.get(*action) {
+                Some(rule) => rule.into(),
+                None if *privileged => Permission::mods_only(mod_roles),
+                None => Permission::everyone(),
+            };
+            (action.to_string(), permission)
+        })
+        .collect()
+}
+
+fn is_allowed(
+    settings: &Settings,
+    action: &str,
+    member: &Member,
+    owner_id: Option<UserId>,
+) -> bool {
+    settings
+        .permissions
+        .get(action)
+        .is_some_and(|p| p.allows(member, owner_id))
+}
+
+/// Check whether a guild member may run a command or press a button.
+pub(crate) fn check(globals: &Globals, action: &str, member: &Member) -> bool {
+    let owner_id = globals
+        .guild_id
+        .to_guild_cached(&globals.context.cache)
+        .map(|guild| guild.owner_id);
+    is_allowed(&globals.settings(), action, member, owner_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use poise::serenity_prelude::Permissions;
+
+    use super::*;
+    use crate::testing;
+
+    const OWNER: u64 = 50;
+
+    fn member(id: u64, roles: &[u64], administrator: bool) -> Member {
+        let mut member = Member::default();
+        member.user.id = UserId::new(id);
+        member.roles = roles.iter().map(|id| RoleId::new(*id)).collect();
+        if administrator {
+            member.permissions = Some(Permissions::ADMINISTRATOR);
+        }
+        member
+    }
+
+    fn rules(rules: serde_json::Value) -> HashMap<String, PermissionRule> {
+        serde_json::from_value(rules).unwrap()
+    }
+
+    #[test]
+    fn defaults() {
+        // the test settings make role 2 the mod role
+        let settings = testing::settings();
+        let moderator = member(10, &[2], false);
+        let player = member(11, &[], false);
+        let owner_id = Some(UserId::new(OWNER));
+
+        assert!(is_allowed(&settings, "check", &player, owner_id));
+        assert!(is_allowed(&settings, "reload", &moderator, owner_id));
+        assert!(!is_allowed(&settings, "reload", &player, owner_id));
+        assert!(is_allowed(
+            &settings,
+            "namereq_deny",
+            &member(12, &[], true),
+            owner_id
+        ));
+        assert!(is_allowed(
+            &settings,
+            "namereq_deny",
+            &member(OWNER, &[], false),
+            owner_id
+        ));
+        assert!(!is_allowed(
+            &settings,
+            "not_an_action",
+            &moderator,
+            owner_id
+        ));
+    }
+
+    #[test]
+    fn rules_replace_defaults() {
+        let settings = testing::settings_with(serde_json::json!({
+            "permissions": {
+                "namereqs": { "roles": [3], "users": [13] },
+                "export": { "roles": [2], "administrator": false, "owner": false },
+                "check": { "everyone": false }
+            }
+        }));
+        let owner_id = Some(UserId::new(OWNER));
+
+        // the rule stands in for the mod roles rather than adding to them
+        assert!(!is_allowed(
+            &settings,
+            "namereqs",
+            &member(10, &[2], false),
+            owner_id
+        ));
+        assert!(is_allowed(
+            &settings,
+            "namereqs",
+            &member(10, &[3], false),
+            owner_id
+        ));
+        assert!(is_allowed(
+            &settings,
+            "namereqs",
+            &member(13, &[], false),
+            owner_id
+        ));
+        assert!(is_allowed(
+            &settings,
+            "namereqs",
+            &member(12, &[], true),
+            owner_id
+        ));
+
+        assert!(is_allowed(
+            &settings,
+            "export",
+            &member(10, &[2], false),
+            owner_id
+        ));
+        assert!(!is_allowed(
+            &settings,
+            "export",
+            &member(12, &[], true),
+            owner_id
+        ));
+        assert!(!is_allowed(
+            &settings,
+            "export",
+            &member(OWNER, &[], false),
+            owner_id
+        ));
+
+        assert!(!is_allowed(
+            &settings,
+            "check",
+            &member(11, &[], false),
+            owner_id
+        ));
+        // untouched actions keep their defaults
+        assert!(is_allowed(
+            &settings,
+            "reload",
+            &member(10, &[2], false),
+            owner_id
+        ));
+    }
+
+    #[test]
+    fn bad_rules_are_rejected() {
+        assert_eq!(
+            validate_rules(&rules(serde_json::json!({ "check": {} }))),
+            None
+        );
+        assert_eq!(
+            validate_rules(&rules(serde_json::json!({ "ban": {} }))),
+            Some("Unknown command or action in permissions: ban".to_string())
+        );
+        assert_eq!(
+            validate_rules(&rules(serde_json::json!({ "export": { "roles": [0] } }))),
+            Some("Invalid role or user ID in permissions for export".to_string())
+        );
+        assert_eq!(
+            validate_rules(&rules(serde_json::json!({ "export": { "users": [0] } }))),
+            Some("Invalid role or user ID in permissions for export".to_string())
+        );
+
+        let resolved = resolve(&HashMap::new(), &HashSet::from([RoleId::new(2)]));
+        assert_eq!(resolved.len(), ACTIONS.len());
+        assert_eq!(
+            resolved["search"].get_roles().collect::<Vec<_>>(),
+            [RoleId::new(2)]
+        );
+        assert_eq!(resolved["check"].get_roles().count(), 0);
+    }
+}
@@ -0,0 +1,152 @@
+use std::fmt::Display;
+
+use poise::serenity_prelude::{ChannelId, Member, Mention, PartialGuild, Permissions};
+
+use crate::{endpoint, Globals};
+
+#[derive(Debug)]
+struct Check {
+    ok: bool,
+    description: String,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Report {
+    checks: Vec<Check>,
+}
+impl Report {
+    fn pass(&mut self, description: impl Into<String>) {
+        self.checks.push(Check {
+            ok: true,
+            description: description.into(),
+        });
+    }
+
+    fn fail(&mut self, description: impl Into<String>) {
+        self.checks.push(Check {
+            ok: false,
+            description: description.into(),
+        });
+    }
+
+    pub(crate) fn num_problems(&self) -> usize {
+        self.checks.iter().filter(|c| !c.ok).count()
+    }
+}
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for check in &self.checks {
+            let icon = if check.ok {
+                ":white_check_mark:"
+            } else {
+                ":x:"
+            };
+            writeln!(f, "{} {}", icon, check.description)?;
+        }
+        match self.num_problems() {
+            0 => write!(f, "All checks passed"),
+            1 => write!(f, "**1** problem found"),
+            n => write!(f, "**{}** problems found", n),
+        }
+    }
+}
+
+/// Channels the bot posts to, along with the permissions it needs in each of them.
+fn get_channel_requirements(globals: &Globals) -> Vec<(&'static str, ChannelId, Permissions)> {
+    let settings = globals.settings();
+    let send = Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES;
+
+    let mut reqs = vec![("mod channel", settings.mod_channel, send)];
+    if let Some(channel) = settings.log_channel {
+        reqs.push(("log channel", channel, send));
+    }
+    if let Some(channel) = settings.name_approvals_channel {
+        reqs.push(("name approvals channel", channel, send));
+    }
+    reqs
+}
+
+async fn check_channel(
+    globals: &Globals,
+    guild: &PartialGuild,
+    bot_member: &Member,
+    report: &mut Report,
+    (name, channel_id, required): (&str, ChannelId, Permissions),
+) {
+    let http = &globals.context.http;
+    let channel = match channel_id.to_channel(http).await {
+        Ok(channel) => channel,
+        Err(e) => {
+            report.fail(format!(
+                "The {} ({}) couldn't be found: {}",
+                name, channel_id, e
+            ));
+            return;
+        }
+    };
+    let Some(channel) = channel.guild().filter(|c| c.guild_id == guild.id) else {
+        report.fail(format!("The {} ({}) isn't in the guild", name, channel_id));
+        return;
+    };
+
+    let missing = required - guild.user_permissions_in(&channel, bot_member);
+    if missing.is_empty() {
+        report.pass(format!("The {} is {}", name, Mention::from(channel_id)));
+    } else {
+        report.fail(format!(
+            "Missing permissions in the {} ({}): {}",
+            name,
+            Mention::from(channel_id),
+            missing.get_permission_names().join(", ")
+        ));
+    }
+}
+
+/// Verify that the guild, channels and roles in the config exist
+/// and that the bot has the permissions it needs to use them.
+pub(crate) async fn run(globals: &Globals) -> Report {
+    let mut report = Report::default();
+    let http = &globals.context.http;
+
+    let guild = match globals.guild_id.to_partial_guild(http).await {
+        Ok(guild) => {
+            report.pass(format!("Guild is {} ({})", guild.name, guild.id));
+            guild
+        }
+        Err(e) => {
+            report.fail(format!(
+                "Guild {} couldn't be found: {}",
+                globals.guild_id, e
+            ));
+            return report;
+        }
+    };
+
+    let settings = globals.settings();
+    for role in settings.get_all_roles() {
+        let is_mod_role = settings.mod_roles.contains(&role);
+        match guild.roles.get(&role) {
+            Some(role) if is_mod_role => report.pass(format!("Mod role is {}", role.name)),
+            Some(role) => report.pass(format!("Role {} is used in permissions", role.name)),
+            None => report.fail(format!("Role {} doesn't exist in the guild", role)),
+        }
+    }
+
+    let bot_member = match guild.id.member(http, globals.bot_user.id).await {
+        Ok(member) => member,
+        Err(e) => {
+            report.fail(format!("Bot isn't a member of the guild: {}", e));
+            return report;
+        }
+    };
+    for req in get_channel_requirements(globals) {
+        check_channel(globals, &guild, &bot_member, &mut report, req).await;
+    }
+
+    match endpoint::get_outstanding_namereqs(&globals.ofapi).await {
+        Ok(_) => report.pass("OFAPI credentials are valid"),
+        Err(e) => report.fail(format!("OFAPI check failed: {}", e)),
+    }
+
+    report
+}
@@ -0,0 +1,136 @@
+use std::{
+    fs::{File, OpenOptions},
+    future::Future,
+    io::{BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::{events::Notification, Result};
+
+/// One line of a recording
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    /// When the notification was received, in milliseconds since the Unix epoch
+    at_ms: u64,
+    notification: Notification,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Writes every monitor notification to a JSON Lines file as it comes in.
+#[derive(Debug)]
+pub(crate) struct Recorder {
+    path: PathBuf,
+    file: Mutex<BufWriter<File>>,
+}
+impl Recorder {
+    /// Starts a new recording in `dir`, named after the current time.
+    /// Never overwrites an earlier recording, even one started in the same second.
+    pub(crate) fn create(dir: &str) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let secs = now_ms() / 1000;
+        let mut suffix = 0;
+        loop {
+            let name = match suffix {
+                0 => format!("monitor-{}.jsonl", secs),
+                n => format!("monitor-{}-{}.jsonl", secs, n),
+            };
+            let path = Path::new(dir).join(name);
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => {
+                    return Ok(Self {
+                        path,
+                        file: Mutex::new(BufWriter::new(file)),
+                    })
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => suffix += 1,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn record(&self, notification: &Notification) -> Result<()> {
+        let entry = Entry {
+            at_ms: now_ms(),
+            notification: notification.clone(),
+        };
+        let mut file = self.file.lock().unwrap();
+        serde_json::to_writer(&mut *file, &entry)?;
+        file.write_all(b"\n")?;
+        // flush every time so nothing is lost if the bot dies
+        file.flush()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Replay {
+    pub(crate) path: String,
+    pub(crate) speed: f64,
+}
+
+/// Feeds a recording to `handle` along with when each notification was received,
+/// keeping the original gaps between notifications (scaled by the replay speed).
+pub(crate) async fn replay<F, Fut>(replay: &Replay, mut handle: F) -> Result<()>
+where
+    F: FnMut(Notification, SystemTime) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let file = BufReader::new(tokio::fs::File::open(&replay.path).await?);
+    let mut lines = file.lines();
+    let mut last_at_ms = None;
+    let mut i = 0;
+    while let Some(line) = lines.next_line().await? {
+        i += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: Entry = serde_json::from_str(&line)
+            .map_err(|e| format!("Bad entry on line {} of {}: {}", i, replay.path, e))?;
+
+        if let Some(last_at_ms) = last_at_ms {
+            if replay.speed > 0.0 {
+                let gap = entry.at_ms.saturating_sub(last_at_ms) as f64 / replay.speed;
+                tokio::time::sleep(Duration::from_millis(gap as u64)).await;
+            }
+        }
+        last_at_ms = Some(entry.at_ms);
+
+        let at = UNIX_EPOCH + Duration::from_millis(entry.at_ms);
+        if let Err(e) = handle(entry.notification, at).await {
+            println!("Error while handling monitor event: {:?}", e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recordings_are_not_overwritten() {
+        let dir =
+            std::env::temp_dir().join(format!("computress-recordings-{}", std::process::id()));
+        let dir = dir.to_str().unwrap();
+        let first = Recorder::create(dir).unwrap();
+        let second = Recorder::create(dir).unwrap();
+        let (first, second) = (first.path().to_owned(), second.path().to_owned());
+        let _ = std::fs::remove_dir_all(dir);
+        assert_ne!(first, second);
+    }
+}
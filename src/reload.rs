@@ -0,0 +1,212 @@
+use std::{
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    config::{Config, Settings},
+    endpoint::OfapiOptions,
+    Globals, Result,
+};
+
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+pub(crate) fn get_mtime(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// What the bot is running with, for the parts of the config a reload can't change.
+#[derive(Debug)]
+struct Running<'a> {
+    guild_id: u64,
+    monitor_address: String,
+    archive_path: &'a Path,
+    archive_retention: Option<Duration>,
+    ofapi: &'a OfapiOptions,
+}
+impl<'a> From<&'a Globals> for Running<'a> {
+    fn from(globals: &'a Globals) -> Self {
+        Self {
+            guild_id: globals.guild_id.get(),
+            monitor_address: globals.monitor.address(),
+            archive_path: globals.archive.path(),
+            archive_retention: globals.archive.retention(),
+            ofapi: &globals.ofapi.options,
+        }
+    }
+}
+
+fn diff_restart_only(running: &Running, config: &Config) -> Vec<String> {
+    let mut changes = Vec::new();
+    if running.guild_id != config.guild_id {
+        changes.push("guild_id (requires restart)".to_string());
+    }
+    if running.monitor_address != config.monitor_address {
+        changes.push("monitor_address (requires restart or /monitor address)".to_string());
+    }
+    if running.archive_path != Path::new(config.get_archive_path()) {
+        changes.push("archive_path (requires restart)".to_string());
+    }
+    if running.archive_retention != config.get_archive_retention() {
+        changes.push("archive_retention_days (requires restart)".to_string());
+    }
+    if OfapiOptions::from_config(config).ok().as_ref() != Some(running.ofapi) {
+        changes.push("OFAPI connection settings (requires restart)".to_string());
+    }
+    changes
+}
+
+/// Re-reads the config file and swaps in the new settings if it's valid.
+/// The outcome is reported to the mod channel either way.
+pub(crate) async fn reload_config(globals: &Globals, trigger: &str) -> Result<Vec<String>> {
+    let path = &globals.config_path;
+    globals.state.lock().await.config_mtime = get_mtime(path);
+
+    let config = match Config::load(path) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("Config reload ({}) rejected: {}", trigger, e);
+            let msg = format!(
+                "Config reload ({}) **rejected** :no_entry:\n{}\nThe previous config is still in effect.",
+                trigger, e
+            );
+            globals
+                .sink
+                .send(globals.settings().mod_channel, &msg)
+                .await?;
+            let alert = format!("Config reload ({}) rejected", trigger);
+            globals
+                .state
+                .lock()
+                .await
+                .digest
+                .record_alert(&alert, SystemTime::now());
+            return Err(e);
+        }
+    };
+
+    let new_settings = Settings::from(&config);
+    let mut changes = {
+        let mut settings = globals.settings.write().unwrap();
+        let changes = settings.diff(&new_settings);
+        *settings = Arc::new(new_settings);
+        changes
+    };
+    changes.extend(diff_restart_only(&Running::from(globals), &config));
+
+    println!("Config reloaded ({}): {} change(s)", trigger, changes.len());
+    let msg = if changes.is_empty() {
+        format!("Config reloaded ({}) with no changes", trigger)
+    } else {
+        let lines: Vec<String> = changes.iter().map(|c| format!("- {}", c)).collect();
+        format!(
+            "Config reloaded ({}) :white_check_mark:\n{}",
+            trigger,
+            lines.join("\n")
+        )
+    };
+    // the new settings are already in effect, so a failed report doesn't fail the reload
+    if let Err(e) = globals
+        .sink
+        .send(globals.settings().mod_channel, &msg)
+        .await
+    {
+        println!("Couldn't report config reload: {}", e);
+    }
+    Ok(changes)
+}
+
+/// Reloads the config whenever the file's modification time changes.
+pub(crate) async fn watch_config_file(globals: Arc<Globals>) {
+    let mut interval = tokio::time::interval(CONFIG_POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        let mtime = get_mtime(&globals.config_path);
+        let last_mtime = globals.state.lock().await.config_mtime;
+        if mtime.is_none() || mtime == last_mtime {
+            continue;
+        }
+        if let Err(e) = reload_config(&globals, "file changed").await {
+            println!("Error while reloading config: {}", e);
+        }
+    }
+}
+
+/// Reloads the config whenever the process receives SIGHUP.
+#[cfg(unix)]
+pub(crate) async fn watch_sighup(globals: Arc<Globals>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            println!("Couldn't listen for SIGHUP: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        if let Err(e) = reload_config(&globals, "SIGHUP").await {
+            println!("Error while reloading config: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn restart_only_changes() {
+        let config = testing::config_with(json!({}));
+        let ofapi = OfapiOptions::from_config(&config).unwrap();
+        let running = Running {
+            guild_id: config.guild_id,
+            monitor_address: config.monitor_address.clone(),
+            archive_path: Path::new(config.get_archive_path()),
+            archive_retention: config.get_archive_retention(),
+            ofapi: &ofapi,
+        };
+        let diff = |overrides| diff_restart_only(&running, &testing::config_with(overrides));
+
+        assert!(diff(json!({})).is_empty());
+        // applied live, so not listed here
+        assert!(diff(json!({ "log_channel_id": 5, "mod_role_ids": [3] })).is_empty());
+        // the same endpoint written differently
+        assert!(diff(json!({ "ofapi_endpoint": "https://api.example.xyz/" })).is_empty());
+
+        assert_eq!(
+            diff(json!({ "guild_id": 9 })),
+            ["guild_id (requires restart)"]
+        );
+        assert_eq!(
+            diff(json!({ "monitor_address": "10.0.0.1:8003" })),
+            ["monitor_address (requires restart or /monitor address)"]
+        );
+        assert_eq!(
+            diff(json!({ "archive_path": "elsewhere.jsonl", "archive_retention_days": 0 })),
+            [
+                "archive_path (requires restart)",
+                "archive_retention_days (requires restart)"
+            ]
+        );
+        for ofapi in [
+            json!({ "ofapi_endpoint": "http://api.example.xyz" }),
+            json!({ "ofapi_timeout_secs": 30 }),
+            json!({ "ofapi_connect_timeout_secs": 3 }),
+            json!({ "ofapi_ca_certs": ["ca.pem"] }),
+            json!({ "ofapi_proxy": "http://proxy.example.xyz:3128" }),
+            json!({ "ofapi_login_path": "/login" }),
+        ] {
+            assert_eq!(
+                diff(ofapi.clone()),
+                ["OFAPI connection settings (requires restart)"],
+                "{}",
+                ofapi
+            );
+        }
+    }
+}
@@ -0,0 +1,181 @@
+//! Pings moderators about name requests that have been waiting too long.
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use poise::serenity_prelude::{Mention, RoleId};
+
+use crate::{config::Reminders, endpoint, util, Globals, NameRequest};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Whether a reminder can go out at `now`.
+fn is_due(reminders: &Reminders, last_reminder: Option<SystemTime>, now: SystemTime) -> bool {
+    if reminders
+        .quiet_hours
+        .is_some_and(|quiet| quiet.contains(now))
+    {
+        return false;
+    }
+    last_reminder.is_none_or(|last| now >= last + reminders.every)
+}
+
+/// How many requests have waited at least `stale_after`, and how long the oldest has waited.
+fn find_stale(
+    outstanding: &[NameRequest],
+    first_seen: &HashMap<NameRequest, SystemTime>,
+    now: SystemTime,
+    stale_after: Duration,
+) -> Option<(usize, Duration)> {
+    let ages: Vec<Duration> = outstanding
+        .iter()
+        .filter_map(|req| first_seen.get(req))
+        .map(|seen| now.duration_since(*seen).unwrap_or_default())
+        .filter(|age| *age >= stale_after)
+        .collect();
+    let oldest = ages.iter().max()?;
+    Some((ages.len(), *oldest))
+}
+
+fn format_reminder(role: RoleId, count: usize, oldest: Duration, stale_after: Duration) -> String {
+    let requests = if count == 1 {
+        "name request has"
+    } else {
+        "name requests have"
+    };
+    format!(
+        "{} **{}** {} been waiting more than {}. The oldest has waited **{}**.",
+        Mention::from(role),
+        count,
+        requests,
+        util::format_duration(stale_after),
+        util::format_duration(oldest)
+    )
+}
+
+/// Checks the name request queue now and then, reminding moderators when requests go stale.
+/// OFAPI doesn't say when requests were made, so ages count from when the bot first saw them.
+pub(crate) async fn run(globals: Arc<Globals>) {
+    let mut first_seen: HashMap<NameRequest, SystemTime> = HashMap::new();
+    let mut last_reminder = None;
+    loop {
+        tokio::time::sleep(CHECK_INTERVAL).await;
+        let settings = globals.settings();
+        let (Some(reminders), Some(channel)) =
+            (settings.namereq_reminders, settings.name_approvals_channel)
+        else {
+            continue;
+        };
+        let now = SystemTime::now();
+        if !is_due(&reminders, last_reminder, now) {
+            continue;
+        }
+
+        let outstanding = match endpoint::get_outstanding_namereqs(&globals.ofapi).await {
+            Ok(reqs) => reqs,
+            Err(e) => {
+                println!("Couldn't check for stale name requests: {}", e);
+                continue;
+            }
+        };
+        first_seen.retain(|req, _| outstanding.contains(req));
+        for req in &outstanding {
+            if !first_seen.contains_key(req) {
+                let seen = globals.archive.name_request_seen(req).unwrap_or(now);
+                first_seen.insert(req.clone(), seen);
+            }
+        }
+        let Some((count, oldest)) =
+            find_stale(&outstanding, &first_seen, now, reminders.stale_after)
+        else {
+            continue;
+        };
+
+        let Some(_task) = globals.shutdown.begin() else {
+            return;
+        };
+        let content = format_reminder(reminders.role, count, oldest, reminders.stale_after);
+        match globals
+            .sink
+            .send_pinging(channel, &content, reminders.role)
+            .await
+        {
+            Ok(_) => last_reminder = Some(now),
+            Err(e) => println!("Couldn't send name request reminder: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use super::*;
+    use crate::testing;
+
+    const HOUR: u64 = 60 * 60;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn namereq(player_uid: u64) -> NameRequest {
+        NameRequest {
+            player_uid,
+            requested_name: "Bob".to_string(),
+        }
+    }
+
+    #[test]
+    fn quiet_hours_and_interval() {
+        let settings = testing::settings_with(serde_json::json!({
+            "namereq_reminders": {
+                "role_id": 5,
+                "every_hours": 2,
+                "quiet_hours": { "start": "22:00", "end": "08:00" }
+            }
+        }));
+        let reminders = settings.namereq_reminders.unwrap();
+        assert_eq!(reminders.stale_after, Duration::from_secs(24 * HOUR));
+
+        assert!(is_due(&reminders, None, at(12 * HOUR)));
+        assert!(!is_due(&reminders, None, at(23 * HOUR)));
+        assert!(!is_due(&reminders, None, at(24 * HOUR + 7 * HOUR)));
+        assert!(is_due(&reminders, None, at(24 * HOUR + 8 * HOUR)));
+        assert!(!is_due(&reminders, Some(at(12 * HOUR)), at(13 * HOUR)));
+        assert!(is_due(&reminders, Some(at(12 * HOUR)), at(14 * HOUR)));
+    }
+
+    #[test]
+    fn stale_requests() {
+        let outstanding = vec![namereq(1), namereq(2), namereq(3)];
+        let first_seen = HashMap::from([
+            (namereq(1), at(0)),
+            (namereq(2), at(10 * HOUR)),
+            (namereq(3), at(30 * HOUR)),
+        ]);
+        let stale_after = Duration::from_secs(24 * HOUR);
+
+        let (count, oldest) =
+            find_stale(&outstanding, &first_seen, at(36 * HOUR), stale_after).unwrap();
+        assert_eq!((count, oldest), (2, Duration::from_secs(36 * HOUR)));
+        assert_eq!(
+            format_reminder(RoleId::new(5), count, oldest, stale_after),
+            "<@&5> **2** name requests have been waiting more than 1d 0h 0m. \
+             The oldest has waited **1d 12h 0m**."
+        );
+
+        // nothing to say when the queue is empty or everything is recent
+        assert_eq!(
+            find_stale(&[], &first_seen, at(36 * HOUR), stale_after),
+            None
+        );
+        assert_eq!(
+            find_stale(&outstanding, &first_seen, at(20 * HOUR), stale_after),
+            None
+        );
+    }
+}
@@ -0,0 +1,192 @@
+//! Stopping the bot cleanly on SIGTERM/SIGINT.
+
+use std::{
+    process::exit,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
+    time::Duration,
+};
+
+use poise::serenity_prelude::ShardManager;
+use tokio::sync::Notify;
+
+use crate::{archive::Archive, Globals};
+
+/// How long to wait for in-flight work before stopping anyway
+const SHUTDOWN_DEADLINE: Duration = Duration::from_secs(10);
+
+/// Stopped after being asked to, with nothing left unfinished
+pub(crate) const EXIT_OK: i32 = 0;
+/// Stopped because of an error
+pub(crate) const EXIT_ERROR: i32 = 1;
+/// Stopped after being asked to, but some work didn't finish in time
+pub(crate) const EXIT_INCOMPLETE: i32 = 2;
+/// Killed by a second signal while stopping
+pub(crate) const EXIT_FORCED: i32 = 3;
+
+/// Tracks whether the bot is stopping and how much work is still in flight.
+#[derive(Debug, Default)]
+pub(crate) struct Shutdown {
+    stopping: AtomicBool,
+    incomplete: AtomicBool,
+    in_flight: AtomicUsize,
+    idle: Notify,
+}
+impl Shutdown {
+    pub(crate) fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    /// Registers a piece of work that should finish before the bot stops.
+    /// Returns `None` once the bot is stopping, in which case the work shouldn't be started.
+    pub(crate) fn begin(self: &Arc<Self>) -> Option<Task> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let task = Task(self.clone());
+        // checked after registering so drain() can't miss work that slipped in
+        if self.is_stopping() {
+            return None;
+        }
+        Some(task)
+    }
+
+    /// Waits for in-flight work to finish, returning false if it didn't in time.
+    async fn drain(&self, deadline: Duration) -> bool {
+        let wait = async {
+            loop {
+                let idle = self.idle.notified();
+                if self.in_flight.load(Ordering::SeqCst) == 0 {
+                    return;
+                }
+                idle.await;
+            }
+        };
+        tokio::time::timeout(deadline, wait).await.is_ok()
+    }
+
+    pub(crate) fn exit_code(&self) -> i32 {
+        if !self.is_stopping() {
+            // the client stopped without being asked to
+            EXIT_ERROR
+        } else if self.incomplete.load(Ordering::SeqCst) {
+            EXIT_INCOMPLETE
+        } else {
+            EXIT_OK
+        }
+    }
+}
+
+/// In-flight work; the bot won't stop (until the deadline) while one of these is alive.
+#[derive(Debug)]
+pub(crate) struct Task(Arc<Shutdown>);
+impl Drop for Task {
+    fn drop(&mut self) {
+        if self.0.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() -> &'static str {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            println!("Couldn't listen for SIGTERM: {}", e);
+            let _ = tokio::signal::ctrl_c().await;
+            return "SIGINT";
+        }
+    };
+    tokio::select! {
+        _ = terminate.recv() => "SIGTERM",
+        _ = tokio::signal::ctrl_c() => "SIGINT",
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() -> &'static str {
+    let _ = tokio::signal::ctrl_c().await;
+    "Ctrl+C"
+}
+
+/// Waits for SIGTERM or SIGINT, then stops taking new work, closes the monitor
+/// connection, lets in-flight work finish, says goodbye in the mod channel and
+/// shuts the shards down. Installed before connecting to Discord, so `globals`
+/// is only set once the bot is ready; before then there's only the archive to close.
+pub(crate) async fn watch_signals(
+    shutdown: Arc<Shutdown>,
+    archive: Arc<Archive>,
+    globals: Arc<OnceLock<Arc<Globals>>>,
+    shard_manager: Arc<ShardManager>,
+) {
+    let signal = wait_for_signal().await;
+    println!("Received {}, stopping", signal);
+    shutdown.stopping.store(true, Ordering::SeqCst);
+    // the supervisor counts as in-flight work, so the drain waits for it to let go
+    if let Some(globals) = globals.get() {
+        globals.monitor.stop();
+    }
+
+    tokio::spawn(async {
+        let signal = wait_for_signal().await;
+        println!("Received {} again, exiting immediately", signal);
+        exit(EXIT_FORCED);
+    });
+
+    if !shutdown.drain(SHUTDOWN_DEADLINE).await {
+        let remaining = shutdown.in_flight.load(Ordering::SeqCst);
+        println!(
+            "Gave up waiting for {} unfinished task(s) after {:?}",
+            remaining, SHUTDOWN_DEADLINE
+        );
+        shutdown.incomplete.store(true, Ordering::SeqCst);
+    }
+
+    // the process exits without dropping anything, so the archive has to be closed by hand
+    if let Err(e) = tokio::task::spawn_blocking(move || archive.close()).await {
+        println!("Couldn't close the archive: {}", e);
+    }
+
+    if let Some(globals) = globals.get() {
+        if let Err(e) = globals
+            .sink
+            .send(globals.settings().mod_channel, "Bot stopping")
+            .await
+        {
+            println!("Couldn't send stop message: {}", e);
+        }
+    }
+
+    shard_manager.shutdown_all().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn drain_waits_for_tasks() {
+        let shutdown = Arc::new(Shutdown::default());
+        let task = shutdown.begin().unwrap();
+        shutdown.stopping.store(true, Ordering::SeqCst);
+        assert!(shutdown.begin().is_none());
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            drop(task);
+        });
+        assert!(shutdown.drain(Duration::from_secs(5)).await);
+        assert_eq!(shutdown.exit_code(), EXIT_OK);
+    }
+
+    #[tokio::test]
+    async fn drain_gives_up_at_deadline() {
+        let shutdown = Arc::new(Shutdown::default());
+        let _task = shutdown.begin().unwrap();
+        shutdown.stopping.store(true, Ordering::SeqCst);
+        assert!(!shutdown.drain(Duration::from_millis(50)).await);
+    }
+}
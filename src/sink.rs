@@ -0,0 +1,199 @@
+//! Where the bot's output goes. Discord in production, but the monitor
+//! pipeline doesn't need to know that.
+
+use std::{
+    fmt::Debug,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use poise::serenity_prelude::{
+    ActivityData, ButtonStyle, ChannelId, Context, CreateActionRow, CreateAllowedMentions,
+    CreateButton, CreateEmbed, CreateMessage, Message, MessageId, RoleId, Timestamp,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+/// A message the bot has sent, so it can be deleted later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub(crate) struct MessageRef {
+    pub(crate) channel: ChannelId,
+    pub(crate) id: MessageId,
+}
+impl From<&Message> for MessageRef {
+    fn from(message: &Message) -> Self {
+        Self {
+            channel: message.channel_id,
+            id: message.id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Button {
+    pub(crate) id: String,
+    pub(crate) label: String,
+    pub(crate) style: ButtonStyle,
+}
+impl Button {
+    pub(crate) fn new(id: &str, label: &str, style: ButtonStyle) -> Self {
+        Self {
+            id: id.to_string(),
+            label: label.to_string(),
+            style,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct EmbedField {
+    pub(crate) name: String,
+    pub(crate) value: String,
+    pub(crate) inline: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct Embed {
+    pub(crate) title: Option<String>,
+    pub(crate) description: Option<String>,
+    pub(crate) colour: u32,
+    pub(crate) fields: Vec<EmbedField>,
+    pub(crate) timestamp: Option<SystemTime>,
+}
+impl Embed {
+    pub(crate) fn field(mut self, name: &str, value: &str, inline: bool) -> Self {
+        self.fields.push(EmbedField {
+            name: name.to_string(),
+            value: value.to_string(),
+            inline,
+        });
+        self
+    }
+}
+impl From<Embed> for CreateEmbed {
+    fn from(embed: Embed) -> Self {
+        let mut create = CreateEmbed::new().colour(embed.colour);
+        if let Some(title) = embed.title {
+            create = create.title(title);
+        }
+        if let Some(description) = embed.description {
+            create = create.description(description);
+        }
+        for field in embed.fields {
+            create = create.field(field.name, field.value, field.inline);
+        }
+        let timestamp = embed
+            .timestamp
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .and_then(|d| Timestamp::from_unix_timestamp(d.as_secs() as i64).ok());
+        if let Some(timestamp) = timestamp {
+            create = create.timestamp(timestamp);
+        }
+        create
+    }
+}
+
+#[async_trait]
+pub(crate) trait Sink: Debug + Send + Sync {
+    async fn send(&self, channel: ChannelId, content: &str) -> Result<MessageRef>;
+
+    async fn send_embed(&self, channel: ChannelId, embed: Embed) -> Result<MessageRef>;
+
+    /// Like [`Sink::send`], but pings `role`, which the content should mention
+    async fn send_pinging(
+        &self,
+        channel: ChannelId,
+        content: &str,
+        role: RoleId,
+    ) -> Result<MessageRef>;
+
+    async fn send_with_buttons(
+        &self,
+        channel: ChannelId,
+        content: &str,
+        buttons: Vec<Button>,
+    ) -> Result<MessageRef>;
+
+    /// Shows "Listening to {text}" as the bot's activity
+    async fn set_presence(&self, text: &str) -> Result<()>;
+
+    async fn delete(&self, message: MessageRef) -> Result<()>;
+}
+
+#[derive(Debug)]
+pub(crate) struct DiscordSink {
+    context: Context,
+}
+impl DiscordSink {
+    pub(crate) fn new(context: Context) -> Self {
+        Self { context }
+    }
+
+    async fn send_message(&self, channel: ChannelId, message: CreateMessage) -> Result<MessageRef> {
+        // nothing the bot relays should ping anyone
+        let message = message.allowed_mentions(CreateAllowedMentions::default().empty_users());
+        let message = channel.send_message(&self.context.http, message).await?;
+        Ok(MessageRef::from(&message))
+    }
+}
+#[async_trait]
+impl Sink for DiscordSink {
+    async fn send(&self, channel: ChannelId, content: &str) -> Result<MessageRef> {
+        self.send_message(channel, CreateMessage::default().content(content))
+            .await
+    }
+
+    async fn send_embed(&self, channel: ChannelId, embed: Embed) -> Result<MessageRef> {
+        self.send_message(channel, CreateMessage::default().embed(embed.into()))
+            .await
+    }
+
+    async fn send_pinging(
+        &self,
+        channel: ChannelId,
+        content: &str,
+        role: RoleId,
+    ) -> Result<MessageRef> {
+        let mentions = CreateAllowedMentions::default()
+            .empty_users()
+            .roles(vec![role]);
+        let message = CreateMessage::default()
+            .content(content)
+            .allowed_mentions(mentions);
+        let message = channel.send_message(&self.context.http, message).await?;
+        Ok(MessageRef::from(&message))
+    }
+
+    async fn send_with_buttons(
+        &self,
+        channel: ChannelId,
+        content: &str,
+        buttons: Vec<Button>,
+    ) -> Result<MessageRef> {
+        let buttons = buttons
+            .into_iter()
+            .map(|b| CreateButton::new(b.id).label(b.label).style(b.style))
+            .collect();
+        let components = vec![CreateActionRow::Buttons(buttons)];
+        let message = CreateMessage::default()
+            .content(content)
+            .components(components);
+        self.send_message(channel, message).await
+    }
+
+    async fn set_presence(&self, text: &str) -> Result<()> {
+        self.context
+            .set_activity(Some(ActivityData::listening(text)));
+        Ok(())
+    }
+
+    async fn delete(&self, message: MessageRef) -> Result<()> {
+        message
+            .channel
+            .delete_message(&self.context.http, message.id)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,300 @@
+//! Owns the connection to the monitor: tracks its status, reconnects with
+//! backoff when it drops, and lets mods force a reconnect or move it elsewhere.
+
+use std::{
+    fmt::Display,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use ffmonitor::{Monitor, MonitorNotification};
+use tokio::sync::{mpsc, Notify};
+
+use crate::{
+    config::Backoff,
+    events::Notification,
+    monitor,
+    util::{self, TimestampStyle},
+    Globals,
+};
+
+#[derive(Debug)]
+pub(crate) struct Status {
+    pub(crate) address: String,
+    pub(crate) connected_since: Option<SystemTime>,
+    /// Connection attempts since the bot started
+    pub(crate) attempts: u64,
+    /// Attempts that failed or connections that dropped since the bot started
+    pub(crate) failures: u64,
+    /// Failures since the last successful connection, for backoff
+    consecutive_failures: u32,
+    pub(crate) last_error: Option<(SystemTime, String)>,
+    pub(crate) next_attempt: Option<SystemTime>,
+}
+impl Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.connected_since {
+            Some(since) => writeln!(
+                f,
+                "Monitor is **connected** :white_check_mark: to `{}` since {}",
+                self.address,
+                util::discord_timestamp(since, TimestampStyle::Relative)
+            )?,
+            None => writeln!(
+                f,
+                "Monitor is **disconnected** :no_entry: from `{}`",
+                self.address
+            )?,
+        }
+        writeln!(
+            f,
+            "Connection attempts: **{}** ({} failed)",
+            self.attempts, self.failures
+        )?;
+        if let Some((at, error)) = &self.last_error {
+            writeln!(
+                f,
+                "Last error {}: {}",
+                util::discord_timestamp(*at, TimestampStyle::Relative),
+                error
+            )?;
+        }
+        if let Some(at) = self.next_attempt {
+            writeln!(
+                f,
+                "Next attempt {}",
+                util::discord_timestamp(at, TimestampStyle::Relative)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Supervisor {
+    status: Mutex<Status>,
+    /// Bumped on every (re)connect so notifications from old connections can be told apart
+    generation: AtomicU64,
+    reconnect: Notify,
+    stop: Notify,
+}
+impl Supervisor {
+    pub(crate) fn new(address: &str) -> Self {
+        Self {
+            status: Mutex::new(Status {
+                address: address.to_string(),
+                connected_since: None,
+                attempts: 0,
+                failures: 0,
+                consecutive_failures: 0,
+                last_error: None,
+                next_attempt: None,
+            }),
+            generation: AtomicU64::new(0),
+            reconnect: Notify::new(),
+            stop: Notify::new(),
+        }
+    }
+
+    /// The address the bot connects to, which `/monitor address` can change until a restart.
+    pub(crate) fn address(&self) -> String {
+        self.status.lock().unwrap().address.clone()
+    }
+
+    /// A snapshot of the connection status, formatted for Discord.
+    pub(crate) fn status(&self) -> String {
+        self.status.lock().unwrap().to_string()
+    }
+
+    /// Drops the current connection and connects again right away,
+    /// optionally to a different address.
+    pub(crate) fn reconnect(&self, address: Option<String>) {
+        {
+            let mut status = self.status.lock().unwrap();
+            if let Some(address) = address {
+                status.address = address;
+            }
+            status.consecutive_failures = 0;
+        }
+        self.reconnect.notify_one();
+    }
+
+    /// Closes the connection for good, as part of shutting down.
+    pub(crate) fn stop(&self) {
+        self.stop.notify_one();
+    }
+
+    fn record_failure(&self, error: String, backoff: &Backoff) -> Duration {
+        let mut status = self.status.lock().unwrap();
+        status.connected_since = None;
+        status.failures += 1;
+        status.consecutive_failures += 1;
+        status.last_error = Some((SystemTime::now(), error));
+        let delay = backoff.delay(status.consecutive_failures);
+        status.next_attempt = Some(SystemTime::now() + delay);
+        delay
+    }
+
+    fn connect(&self, tx: &mpsc::UnboundedSender<(u64, Notification)>) -> Result<Monitor, String> {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let address = {
+            let mut status = self.status.lock().unwrap();
+            status.attempts += 1;
+            status.connected_since = None;
+            status.next_attempt = None;
+            status.address.clone()
+        };
+        println!("Connecting to monitor at {}", address);
+        let tx = tx.clone();
+        let callback = move |notification: MonitorNotification| {
+            let _ = tx.send((generation, notification.into()));
+        };
+        Monitor::new_with_callback(&address, Box::new(callback)).map_err(|e| format!("{:?}", e))
+    }
+}
+
+fn handle(globals: &Arc<Globals>, notification: Notification) {
+    if let Some(recorder) = &globals.recorder {
+        if let Err(e) = recorder.record(&notification) {
+            println!("Error while recording monitor event: {}", e);
+        }
+    }
+    // once stopping, let the rest of the session go unhandled
+    let Some(task) = globals.shutdown.begin() else {
+        return;
+    };
+    let at = SystemTime::now();
+    let globals = globals.clone();
+    tokio::spawn(async move {
+        if let Err(e) = monitor::handle_notification(&globals, notification, at, false).await {
+            println!("Error while handling monitor event: {:?}", e);
+        }
+        drop(task);
+    });
+}
+
+/// Keeps the bot connected to the monitor until [`Supervisor::stop`] is called.
+pub(crate) async fn run(globals: Arc<Globals>) {
+    // shutting down waits for the connection to be closed
+    let Some(_task) = globals.shutdown.begin() else {
+        return;
+    };
+    let supervisor = &globals.monitor;
+    let (tx, mut notifications) = mpsc::unbounded_channel();
+    // kept alive for as long as its connection is the current one
+    let mut connection = None;
+    let mut retry_in = None;
+    loop {
+        // ffmonitor keeps reconnecting on its own, so the old connection has to go
+        // before the backoff starts, or two could end up live at once
+        drop(connection.take());
+        if let Some(delay) = retry_in.take() {
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = supervisor.reconnect.notified() => {}
+                _ = supervisor.stop.notified() => return,
+            }
+        }
+        match supervisor.connect(&tx) {
+            Ok(monitor) => connection = Some(monitor),
+            Err(e) => {
+                let delay = supervisor.record_failure(
+                    format!("Couldn't start monitor: {}", e),
+                    &globals.settings().monitor_backoff,
+                );
+                println!("Couldn't start monitor, retrying in {:?}: {}", delay, e);
+                retry_in = Some(delay);
+                continue;
+            }
+        }
+
+        // pass notifications along until this connection needs replacing
+        loop {
+            let (generation, notification) = tokio::select! {
+                Some(received) = notifications.recv() => received,
+                _ = supervisor.reconnect.notified() => {
+                    println!("Reconnecting to monitor");
+                    break;
+                }
+                _ = supervisor.stop.notified() => {
+                    println!("Closing monitor connection");
+                    return;
+                }
+            };
+            if generation != supervisor.generation.load(Ordering::SeqCst) {
+                continue;
+            }
+            match notification {
+                Notification::Connected => {
+                    let mut status = supervisor.status.lock().unwrap();
+                    status.connected_since = Some(SystemTime::now());
+                    status.consecutive_failures = 0;
+                    status.next_attempt = None;
+                }
+                Notification::Disconnected => {
+                    let was_connected = supervisor.status.lock().unwrap().connected_since.is_some();
+                    let error = if was_connected {
+                        "Connection lost"
+                    } else {
+                        "Couldn't connect"
+                    };
+                    let delay = supervisor
+                        .record_failure(error.to_string(), &globals.settings().monitor_backoff);
+                    println!("{}, reconnecting in {:?}", error, delay);
+                    retry_in = Some(delay);
+                }
+                Notification::Updated { .. } => {}
+            }
+            let disconnected = notification == Notification::Disconnected;
+            handle(&globals, notification);
+            if disconnected {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backoff() -> Backoff {
+        Backoff {
+            min: Duration::from_secs(1),
+            max: Duration::from_secs(10),
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let delays: Vec<u64> = (1..=6).map(|n| backoff().delay(n).as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 10, 10]);
+        assert_eq!(backoff().delay(1000), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn failures_are_reported() {
+        let supervisor = Supervisor::new("127.0.0.1:8003");
+        assert!(supervisor
+            .status()
+            .starts_with("Monitor is **disconnected** :no_entry: from `127.0.0.1:8003`"));
+
+        supervisor.record_failure("Couldn't connect".to_string(), &backoff());
+        let delay = supervisor.record_failure("Couldn't connect".to_string(), &backoff());
+        assert_eq!(delay, Duration::from_secs(2));
+        let status = supervisor.status();
+        assert!(status.contains("Connection attempts: **0** (2 failed)"));
+        assert!(status.contains(": Couldn't connect"));
+        assert!(status.contains("Next attempt <t:"));
+
+        // a forced reconnect starts the backoff over
+        supervisor.reconnect(Some("10.0.0.1:8003".to_string()));
+        let delay = supervisor.record_failure("Couldn't connect".to_string(), &backoff());
+        assert_eq!(delay, Duration::from_secs(1));
+        assert!(supervisor.status().contains("`10.0.0.1:8003`"));
+        assert_eq!(supervisor.address(), "10.0.0.1:8003");
+    }
+}
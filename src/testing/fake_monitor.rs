@@ -0,0 +1,132 @@
+use tokio::{
+    io::AsyncWriteExt,
+    net::TcpListener,
+    sync::mpsc::{self, UnboundedSender},
+    task::JoinHandle,
+};
+
+/// One tick's worth of monitor output, written the way the OpenFusion server's monitor does.
+#[derive(Debug, Default)]
+pub(crate) struct FakeUpdate {
+    lines: Vec<String>,
+}
+impl FakeUpdate {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn player(mut self, name: &str) -> Self {
+        self.lines.push(format!("player 0 0 {}", name));
+        self
+    }
+
+    pub(crate) fn chat(mut self, kind: &str, from: &str, to: Option<&str>, message: &str) -> Self {
+        let line = match to {
+            Some(to) => format!("chat [{}] {} (to {}): {}", kind, from, to, message),
+            None => format!("chat [{}] {}: {}", kind, from, message),
+        };
+        self.lines.push(line);
+        self
+    }
+
+    pub(crate) fn email(
+        mut self,
+        from: &str,
+        to: &str,
+        subject: Option<&str>,
+        body: &[&str],
+    ) -> Self {
+        self.lines.push(format!(
+            "email [Email] {} (to {}): <{}>",
+            from,
+            to,
+            subject.unwrap_or_default()
+        ));
+        for line in body {
+            self.lines.push(format!("\t{}", line));
+        }
+        self.lines.push("endemail".to_string());
+        self
+    }
+
+    pub(crate) fn broadcast(mut self, scope: &str, from: &str, message: &str) -> Self {
+        self.lines
+            .push(format!("bcast [{}] {}: {}", scope, from, message));
+        self
+    }
+
+    pub(crate) fn name_request(mut self, player_uid: u64, requested_name: &str) -> Self {
+        self.lines
+            .push(format!("namereq {} {}", player_uid, requested_name));
+        self
+    }
+
+    fn encode(&self) -> String {
+        let mut out = String::from("begin\n");
+        for line in &self.lines {
+            out.push_str(line);
+            out.push('\n');
+        }
+        out.push_str("end\n");
+        out
+    }
+}
+
+enum Command {
+    Send(String),
+    Disconnect,
+}
+
+/// A local stand-in for an OpenFusion server's monitor port.
+/// Updates are queued until a client connects and are then written to it in order.
+pub(crate) struct FakeMonitor {
+    address: String,
+    commands: UnboundedSender<Command>,
+    server: JoinHandle<()>,
+}
+impl FakeMonitor {
+    pub(crate) async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (commands, mut rx) = mpsc::unbounded_channel();
+
+        let server = tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                while let Some(command) = rx.recv().await {
+                    match command {
+                        Command::Send(data) => {
+                            if stream.write_all(data.as_bytes()).await.is_err() {
+                                break;
+                            }
+                        }
+                        Command::Disconnect => break,
+                    }
+                }
+            }
+        });
+
+        Self {
+            address,
+            commands,
+            server,
+        }
+    }
+
+    pub(crate) fn address(&self) -> String {
+        self.address.clone()
+    }
+
+    pub(crate) fn send(&self, update: FakeUpdate) {
+        let _ = self.commands.send(Command::Send(update.encode()));
+    }
+
+    /// Drops the connection to the current client.
+    pub(crate) fn disconnect(&self) {
+        let _ = self.commands.send(Command::Disconnect);
+    }
+}
+impl Drop for FakeMonitor {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
@@ -0,0 +1,107 @@
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use poise::serenity_prelude::{ChannelId, MessageId, RoleId};
+
+use crate::{
+    sink::{Button, Embed, MessageRef, Sink},
+    Result,
+};
+
+/// Everything a [`MemorySink`] was asked to do, in order.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Sent {
+    Message {
+        message: MessageRef,
+        content: String,
+        buttons: Vec<Button>,
+    },
+    Embed {
+        message: MessageRef,
+        embed: Embed,
+    },
+    Ping {
+        message: MessageRef,
+        content: String,
+        role: RoleId,
+    },
+    Presence(String),
+    Delete(MessageRef),
+}
+
+/// A sink that remembers what it was sent instead of sending it anywhere.
+#[derive(Debug, Default)]
+pub(crate) struct MemorySink {
+    sent: Mutex<Vec<Sent>>,
+    next_id: Mutex<u64>,
+}
+impl MemorySink {
+    pub(crate) fn sent(&self) -> Vec<Sent> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    fn push(&self, sent: Sent) {
+        self.sent.lock().unwrap().push(sent);
+    }
+
+    fn next_ref(&self, channel: ChannelId) -> MessageRef {
+        let mut next_id = self.next_id.lock().unwrap();
+        *next_id += 1;
+        MessageRef {
+            channel,
+            id: MessageId::new(*next_id),
+        }
+    }
+}
+#[async_trait]
+impl Sink for MemorySink {
+    async fn send(&self, channel: ChannelId, content: &str) -> Result<MessageRef> {
+        self.send_with_buttons(channel, content, vec![]).await
+    }
+
+    async fn send_embed(&self, channel: ChannelId, embed: Embed) -> Result<MessageRef> {
+        let message = self.next_ref(channel);
+        self.push(Sent::Embed { message, embed });
+        Ok(message)
+    }
+
+    async fn send_pinging(
+        &self,
+        channel: ChannelId,
+        content: &str,
+        role: RoleId,
+    ) -> Result<MessageRef> {
+        let message = self.next_ref(channel);
+        self.push(Sent::Ping {
+            message,
+            content: content.to_string(),
+            role,
+        });
+        Ok(message)
+    }
+
+    async fn send_with_buttons(
+        &self,
+        channel: ChannelId,
+        content: &str,
+        buttons: Vec<Button>,
+    ) -> Result<MessageRef> {
+        let message = self.next_ref(channel);
+        self.push(Sent::Message {
+            message,
+            content: content.to_string(),
+            buttons,
+        });
+        Ok(message)
+    }
+
+    async fn set_presence(&self, text: &str) -> Result<()> {
+        self.push(Sent::Presence(text.to_string()));
+        Ok(())
+    }
+
+    async fn delete(&self, message: MessageRef) -> Result<()> {
+        self.push(Sent::Delete(message));
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+use crate::endpoint::{OfapiAuth, OfapiClient, OfapiOptions};
+
+#[derive(Debug, Clone)]
+struct MockResponse {
+    status: u16,
+    body: String,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct RecordedRequest {
+    pub(crate) method: String,
+    pub(crate) path: String,
+    headers: Vec<(String, String)>,
+    pub(crate) body: String,
+}
+impl RecordedRequest {
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub(crate) fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.body).unwrap()
+    }
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    responses: HashMap<(String, String), VecDeque<MockResponse>>,
+    requests: Vec<RecordedRequest>,
+}
+impl MockState {
+    fn next_response(&mut self, method: &str, path: &str) -> MockResponse {
+        let key = (method.to_string(), path.to_string());
+        match self.responses.get_mut(&key) {
+            // the last scripted response for a route keeps being served
+            Some(queue) if queue.len() > 1 => queue.pop_front().unwrap(),
+            Some(queue) if !queue.is_empty() => queue[0].clone(),
+            _ => MockResponse {
+                status: 404,
+                body: r#"{"error": "no scripted response"}"#.to_string(),
+            },
+        }
+    }
+}
+
+/// An in-process OFAPI that serves scripted responses and records every request it gets.
+pub(crate) struct MockOfapi {
+    addr: SocketAddr,
+    state: Arc<Mutex<MockState>>,
+    server: JoinHandle<()>,
+}
+impl MockOfapi {
+    pub(crate) const TOKEN: &'static str = "mock-token";
+
+    pub(crate) async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(MockState::default()));
+
+        let server_state = state.clone();
+        let server = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let state = server_state.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, state).await {
+                        println!("Mock OFAPI connection error: {}", e);
+                    }
+                });
+            }
+        });
+
+        Self {
+            addr,
+            state,
+            server,
+        }
+    }
+
+    pub(crate) fn base_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// A client pointed at this server, authenticated with [`Self::TOKEN`].
+    pub(crate) fn client(&self) -> OfapiClient {
+        self.client_with_auth(OfapiAuth::with_token(Self::TOKEN))
+    }
+
+    pub(crate) fn client_with_auth(&self, auth: OfapiAuth) -> OfapiClient {
+        OfapiClient::new(OfapiOptions::with_base_url(self.base_url()), auth).unwrap()
+    }
+
+    /// Queues a response for a route. Responses are served in order,
+    /// and the last one is repeated for any further requests.
+    pub(crate) fn respond(&self, method: &str, path: &str, status: u16, body: &str) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .responses
+            .entry((method.to_string(), path.to_string()))
+            .or_default()
+            .push_back(MockResponse {
+                status,
+                body: body.to_string(),
+            });
+    }
+
+    pub(crate) fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+impl Drop for MockOfapi {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+fn find_header_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(4).position(|w| w == b"\r\n\r\n").map(|i| i + 4)
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    state: Arc<Mutex<MockState>>,
+) -> std::io::Result<()> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 4096];
+    let header_end = loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(end) = find_header_end(&buf) {
+            break end;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buf[..header_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default().to_string();
+    let path = request_line.next().unwrap_or_default().to_string();
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .collect();
+
+    let content_length = headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, v)| v.parse::<usize>().ok())
+        .unwrap_or(0);
+    while buf.len() < header_end + content_length {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    let body = String::from_utf8_lossy(&buf[header_end..]).to_string();
+
+    let response = {
+        let mut state = state.lock().unwrap();
+        let response = state.next_response(&method, &path);
+        state.requests.push(RecordedRequest {
+            method,
+            path,
+            headers,
+            body,
+        });
+        response
+    };
+
+    let raw = format!(
+        "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.body.len(),
+        response.body
+    );
+    stream.write_all(raw.as_bytes()).await?;
+    stream.shutdown().await
+}
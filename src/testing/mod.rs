@@ -0,0 +1,54 @@
+//! Stand-ins for the services the bot talks to, for use in tests.
+
+mod fake_monitor;
+mod memory_sink;
+mod mock_ofapi;
+
+pub(crate) use fake_monitor::{FakeMonitor, FakeUpdate};
+pub(crate) use memory_sink::{MemorySink, Sent};
+pub(crate) use mock_ofapi::MockOfapi;
+
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::config::{Config, Settings};
+
+pub(crate) const MOD_CHANNEL: u64 = 1001;
+pub(crate) const LOG_CHANNEL: u64 = 1002;
+pub(crate) const NAME_APPROVALS_CHANNEL: u64 = 1003;
+
+/// Settings for a bot that logs to [`LOG_CHANNEL`] and posts name requests to [`NAME_APPROVALS_CHANNEL`].
+pub(crate) fn settings() -> Settings {
+    settings_with(serde_json::json!({}))
+}
+
+/// Like [`settings`], with some config keys added or replaced.
+pub(crate) fn settings_with(overrides: serde_json::Value) -> Settings {
+    Settings::from(&config_with(overrides))
+}
+
+/// The config behind [`settings_with`].
+pub(crate) fn config_with(overrides: serde_json::Value) -> Config {
+    let mut config = serde_json::json!({
+        "guild_id": 1,
+        "mod_role_ids": [2],
+        "mod_channel_id": MOD_CHANNEL,
+        "log_channel_id": LOG_CHANNEL,
+        "name_approvals_channel_id": NAME_APPROVALS_CHANNEL,
+        "monitor_address": "127.0.0.1:8003",
+        "ofapi_endpoint": "api.example.xyz",
+    });
+    if let (Some(config), Some(overrides)) = (config.as_object_mut(), overrides.as_object()) {
+        config.extend(overrides.clone());
+    }
+    serde_json::from_value(config).unwrap()
+}
+
+/// A path in the temp directory that no other test will use.
+pub(crate) fn temp_path(name: &str) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!("computress-{}-{}-{}", name, std::process::id(), n))
+}
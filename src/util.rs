@@ -1,25 +1,236 @@
-use poise::serenity_prelude::{ButtonStyle, ChannelId, CreateButton};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::{send_message_with_buttons, NameRequest, Result};
+use poise::serenity_prelude::{ButtonStyle, ChannelId};
 
+use crate::{
+    sink::{Button, MessageRef, Sink},
+    NameRequest, Result,
+};
+
+/// Reads a secret from the environment variable `name`, or from the file
+/// named by `{name}_FILE` (for Docker secrets and systemd credentials).
+pub(crate) fn get_secret(name: &str) -> Result<Option<String>> {
+    if let Ok(value) = std::env::var(name) {
+        return Ok(Some(value));
+    }
+    let file_var = format!("{}_FILE", name);
+    let Ok(path) = std::env::var(&file_var) else {
+        return Ok(None);
+    };
+    match std::fs::read_to_string(&path) {
+        Ok(value) => Ok(Some(value.trim().to_string())),
+        Err(e) => Err(format!("Couldn't read {} ({}): {}", file_var, path, e).into()),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum TimestampStyle {
+    /// "2 minutes ago"
+    Relative,
+    /// "20 April 2021 16:20"
+    ShortDateTime,
+}
+
+/// A timestamp that Discord shows in each reader's own time zone.
+pub(crate) fn discord_timestamp(time: SystemTime, style: TimestampStyle) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let style = match style {
+        TimestampStyle::Relative => 'R',
+        TimestampStyle::ShortDateTime => 'f',
+    };
+    format!("<t:{}:{}>", secs, style)
+}
+
+/// A rough duration like "2d 3h 15m", for reports.
+pub(crate) fn format_duration(duration: Duration) -> String {
+    let minutes = duration.as_secs() / 60;
+    let (days, hours, minutes) = (minutes / (24 * 60), minutes / 60 % 24, minutes % 60);
+    match (days, hours) {
+        (0, 0) => format!("{}m", minutes),
+        (0, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h {}m", days, hours, minutes),
+    }
+}
+
+/// Cuts `text` down to at most `max` characters, marking where it was cut.
+pub(crate) fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let mut cut: String = text.chars().take(max.saturating_sub(1)).collect();
+    cut.push('…');
+    cut
+}
+
+/// Parses a time given in a command, either relative to `now` ("30m", "2h", "7d", "1w")
+/// or as a UTC date with an optional time ("2024-05-01", "2024-05-01 18:30").
+pub(crate) fn parse_time(input: &str, now: SystemTime) -> Result<SystemTime> {
+    let input = input.trim();
+    let invalid = || format!("Couldn't understand the time \"{}\"", input);
+
+    if let Some(unit) = input.chars().last().filter(|c| c.is_ascii_alphabetic()) {
+        let amount: u64 = input[..input.len() - 1].parse().map_err(|_| invalid())?;
+        let unit_secs = match unit.to_ascii_lowercase() {
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return Err(invalid().into()),
+        };
+        let ago = Duration::from_secs(amount.saturating_mul(unit_secs));
+        return Ok(now.checked_sub(ago).unwrap_or(UNIX_EPOCH).max(UNIX_EPOCH));
+    }
+
+    let (date, time) = input.split_once([' ', 'T']).unwrap_or((input, "00:00"));
+    let numbers =
+        |s: &str, sep: char| -> Option<Vec<u64>> { s.split(sep).map(|n| n.parse().ok()).collect() };
+    let (Some(date), Some(time)) = (numbers(date, '-'), numbers(time, ':')) else {
+        return Err(invalid().into());
+    };
+    let (&[year, month, day], &[hour, minute]) = (&date[..], &time[..]) else {
+        return Err(invalid().into());
+    };
+    if !(1970..=9999).contains(&year)
+        || !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+    {
+        return Err(invalid().into());
+    }
+    let days = days_since_epoch(year, month, day);
+    Ok(UNIX_EPOCH + Duration::from_secs(days * 24 * 60 * 60 + hour * 60 * 60 + minute * 60))
+}
+
+/// Days from 1970-01-01 to the given date, for dates after it.
+/// See <http://howardhinnant.github.io/date_algorithms.html#days_from_civil>.
+fn days_since_epoch(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let month_from_march = (month + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The date for a number of days after 1970-01-01, the inverse of [`days_since_epoch`].
+/// See <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+fn date_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+/// An ISO 8601 UTC timestamp, e.g. "2024-05-01T18:30:00Z".
+pub(crate) fn format_utc(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = date_from_days(secs / (24 * 60 * 60));
+    let secs_of_day = secs % (24 * 60 * 60);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
+
+pub(crate) fn format_name_request_message(name_request: &NameRequest) -> String {
+    format!(
+        "Name request from Player {}: **{}**",
+        name_request.player_uid, name_request.requested_name
+    )
+}
+
+/// Posts a name request with approve/deny buttons, followed by `context` lines about the player.
 pub(crate) async fn send_name_request_message(
+    sink: &dyn Sink,
     channel: ChannelId,
     name_request: &NameRequest,
-) -> Result<()> {
-    let messsage = format!(
-        "Name request from Player {}: **{}**",
-        name_request.player_uid, name_request.requested_name
-    );
+    context: &[String],
+) -> Result<MessageRef> {
+    let mut messsage = format_name_request_message(name_request);
+    for line in context {
+        messsage.push('\n');
+        messsage.push_str(line);
+    }
 
     let buttons = vec![
-        CreateButton::new("namereq_approve")
-            .label("Approve")
-            .style(ButtonStyle::Success),
-        CreateButton::new("namereq_deny")
-            .label("Deny")
-            .style(ButtonStyle::Danger),
+        Button::new("namereq_approve", "Approve", ButtonStyle::Success),
+        Button::new("namereq_deny", "Deny", ButtonStyle::Danger),
     ];
 
-    send_message_with_buttons(channel, &messsage, buttons).await?;
-    Ok(())
+    sink.send_with_buttons(channel, &messsage, buttons).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn times() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let parse = |input| {
+            parse_time(input, now)
+                .map(|t| t.duration_since(UNIX_EPOCH).unwrap().as_secs())
+                .ok()
+        };
+        assert_eq!(parse("30m"), Some(1_000_000 - 30 * 60));
+        assert_eq!(parse("2h"), Some(1_000_000 - 2 * 60 * 60));
+        assert_eq!(parse("1w"), Some(1_000_000 - 7 * 24 * 60 * 60));
+        assert_eq!(parse("9999d"), Some(0));
+        assert_eq!(parse("1970-01-02"), Some(24 * 60 * 60));
+        assert_eq!(parse("2024-03-01 18:30"), Some(1_709_317_800));
+        assert_eq!(parse("2000-02-29T00:00"), Some(951_782_400));
+        assert_eq!(parse("yesterday"), None);
+        assert_eq!(parse("2h30m"), None);
+        assert_eq!(parse("2024-13-01"), None);
+        assert_eq!(parse("2024-05"), None);
+    }
+
+    #[test]
+    fn utc_formatting() {
+        assert_eq!(format_utc(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        let time = parse_time("2000-02-29 23:59", UNIX_EPOCH).unwrap();
+        assert_eq!(format_utc(time), "2000-02-29T23:59:00Z");
+        let time = parse_time("2024-12-31 06:05", UNIX_EPOCH).unwrap();
+        assert_eq!(
+            format_utc(time + Duration::from_secs(7)),
+            "2024-12-31T06:05:07Z"
+        );
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(format_duration(Duration::from_secs(59)), "0m");
+        assert_eq!(format_duration(Duration::from_secs(3 * 3600 + 60)), "3h 1m");
+        assert_eq!(format_duration(Duration::from_secs(50 * 3600)), "2d 2h 0m");
+    }
+
+    #[test]
+    fn truncation() {
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("a bit too long", 10), "a bit too…");
+    }
 }